@@ -8,6 +8,13 @@ import serial
 import time
 import re
 
+# Telemetry state letters, see state_letter in src/state.rs:
+STATE_NAMES = {
+    'S': 'Standby', 'C': 'Countdown', 'M': 'MotorBurn', 'O': 'Coast', 'D': 'DrogueDescent',
+    'N': 'MainDescent', 'L': 'Landed', 'R': 'Recovery', 'A': 'Abort', 'Z': 'Fault',
+    'X': 'Shutdown', 'U': 'Unknown',
+}
+
 class SerialThread(QThread):
     data_received = pyqtSignal(tuple)
 
@@ -41,8 +48,8 @@ class SerialThread(QThread):
                                 try:
                                     clean_line = re.sub(r'[^\x20-\x7E]', '', line)
                                     parts = clean_line.strip().split(',')
-                                    if len(parts) == 8 and parts[0] == 'C':
-                                        state_name = parts[0]
+                                    if len(parts) == 8 and parts[0] in STATE_NAMES:
+                                        state_name = STATE_NAMES[parts[0]]
                                         alt = float(parts[1])
                                         vel = float(parts[2])
                                         max_alt = float(parts[3])
@@ -0,0 +1,43 @@
+//! Battery voltage monitoring, read from a sysfs file exposed by a voltage monitor driver
+//! (e.g. the INA219 hwmon driver).
+
+use crate::config::BatteryConfig;
+use crate::warn;
+use std::fs;
+
+pub struct Battery {
+    voltage_path: String,
+    scale: f32,
+}
+
+impl Battery {
+    pub fn new(config: &BatteryConfig) -> Self {
+        Battery {
+            voltage_path: config.voltage_path.clone(),
+            scale: config.scale,
+        }
+    }
+
+    /// Returns the battery voltage in volts, or None if it can't be read.
+    pub fn read_voltage(&self) -> Option<f32> {
+        match fs::read_to_string(&self.voltage_path) {
+            Ok(contents) => match contents.trim().parse::<f32>() {
+                Ok(raw) => Some(raw * self.scale),
+                Err(e) => {
+                    warn!(
+                        "Failed to parse battery voltage from {}: {}",
+                        self.voltage_path, e
+                    );
+                    None
+                }
+            },
+            Err(e) => {
+                warn!(
+                    "Failed to read battery voltage from {}: {}",
+                    self.voltage_path, e
+                );
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Converts a binary flight log to CSV.
+//!
+//! Usage: `salt-log <log.bin> [output.csv]`. The output defaults to `<log>.csv`.
+//!
+//! There's no Parquet output: the `parquet` crate would pull in most of Arrow for a one-off
+//! conversion, and the CSV loads into pandas or polars just as well (which can write Parquet).
+
+use salt::binlog::BinaryLog;
+use salt::logger::write_csv_metadata;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <log.bin> [output.csv]", args[0]);
+        process::exit(1);
+    }
+    let input_path = &args[1];
+    let output_path = match args.get(2) {
+        Some(path) => path.clone(),
+        None => format!(
+            "{}.csv",
+            input_path.strip_suffix(".bin").unwrap_or(input_path)
+        ),
+    };
+
+    let log = match BinaryLog::open(input_path) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input_path, e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = write_csv(&log, &output_path) {
+        eprintln!("Failed to write {}: {}", output_path, e);
+        process::exit(1);
+    }
+
+    println!(
+        "Converted {} records to {}.",
+        log.records.len(),
+        output_path
+    );
+    if log.corrupt_blocks > 0 {
+        println!("Skipped {} blocks with a bad CRC.", log.corrupt_blocks);
+    }
+    if log.truncated {
+        println!("The log ends with an incomplete block, which was dropped.");
+    }
+}
+
+fn write_csv(log: &BinaryLog, path: &str) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_csv_metadata(&mut writer, &log.metadata)?;
+    let names: Vec<&str> = log.fields.iter().map(|field| field.name.as_str()).collect();
+    writeln!(writer, "{}", names.join(","))?;
+    for record in &log.records {
+        let line: Vec<String> = record.iter().map(|value| value.to_string()).collect();
+        writeln!(writer, "{}", line.join(","))?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,173 @@
+//! Salvages a CSV flight log that was cut short by a power loss.
+//!
+//! A log that wasn't closed cleanly can end with a partially written record, or with a block of
+//! zero bytes where the file system had reserved space that was never written. This copies every
+//! complete record to a new file and drops the rest.
+//!
+//! Usage: `salt-recover <log.csv> [output.csv]`. The output defaults to `<log>_recovered.csv`.
+//! Binary logs are rejected: they don't need recovering, since `salt-log` already skips a partly
+//! written block when it converts them.
+
+use salt::binlog::FILE_MAGIC;
+use std::env;
+use std::fs;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <log.csv> [output.csv]", args[0]);
+        process::exit(1);
+    }
+    let input_path = &args[1];
+    let output_path = match args.get(2) {
+        Some(path) => path.clone(),
+        None => format!(
+            "{}_recovered.csv",
+            input_path.strip_suffix(".csv").unwrap_or(input_path)
+        ),
+    };
+
+    let bytes = match fs::read(input_path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input_path, e);
+            process::exit(1);
+        }
+    };
+
+    if bytes.starts_with(FILE_MAGIC) {
+        eprintln!(
+            "{} is a binary log. Convert it with salt-log instead, which drops a partly written \
+             last block.",
+            input_path
+        );
+        process::exit(1);
+    }
+
+    let (recovered, report) = recover(&bytes);
+    if let Err(e) = fs::write(&output_path, recovered) {
+        eprintln!("Failed to write {}: {}", output_path, e);
+        process::exit(1);
+    }
+
+    println!("Recovered {} records to {}.", report.kept, output_path);
+    if report.dropped > 0 {
+        println!("Dropped {} incomplete or corrupt records.", report.dropped);
+    }
+    if report.zero_bytes > 0 {
+        println!("Ignored {} trailing zero bytes.", report.zero_bytes);
+    }
+}
+
+struct RecoveryReport {
+    kept: usize,
+    dropped: usize,
+    zero_bytes: usize,
+}
+
+/// Keeps the metadata comments, the header and every record that is newline-terminated, has as
+/// many columns as the header and starts with a valid timestamp.
+fn recover(bytes: &[u8]) -> (String, RecoveryReport) {
+    // Everything after the first zero byte is unwritten space:
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let zero_bytes = bytes.len() - end;
+    let text = String::from_utf8_lossy(&bytes[..end]);
+
+    let mut report = RecoveryReport {
+        kept: 0,
+        dropped: 0,
+        zero_bytes,
+    };
+    let mut output = String::new();
+    let mut columns = None;
+
+    // split_inclusive keeps the newline, so we can tell whether the last line was finished:
+    for line in text.split_inclusive('\n') {
+        let Some(record) = line.strip_suffix('\n') else {
+            report.dropped += 1;
+            continue;
+        };
+        let record = record.strip_suffix('\r').unwrap_or(record);
+        // Metadata comments come before the column header:
+        if columns.is_none() && record.starts_with('#') {
+            output.push_str(record);
+            output.push('\n');
+            continue;
+        }
+        let field_count = record.split(',').count();
+
+        match columns {
+            None => {
+                columns = Some(field_count);
+                output.push_str(record);
+                output.push('\n');
+            }
+            Some(expected) => {
+                let valid_timestamp = record
+                    .split(',')
+                    .next()
+                    .is_some_and(|timestamp| timestamp.parse::<u64>().is_ok());
+                if field_count == expected && valid_timestamp {
+                    output.push_str(record);
+                    output.push('\n');
+                    report.kept += 1;
+                } else {
+                    report.dropped += 1;
+                }
+            }
+        }
+    }
+
+    (output, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "# version: 1.0\ntimestamp,state,altitude\n100,S,1.5\n200,S,1.6\n";
+
+    #[test]
+    fn keeps_a_complete_log() {
+        let (recovered, report) = recover(LOG.as_bytes());
+        assert_eq!(recovered, LOG);
+        assert_eq!((report.kept, report.dropped, report.zero_bytes), (2, 0, 0));
+    }
+
+    #[test]
+    fn drops_a_partly_written_last_record() {
+        let log = format!("{}300,S,1", LOG);
+        let (recovered, report) = recover(log.as_bytes());
+        assert_eq!(recovered, LOG);
+        assert_eq!((report.kept, report.dropped), (2, 1));
+    }
+
+    #[test]
+    fn drops_unwritten_space() {
+        let mut log = format!("{}300,S", LOG).into_bytes();
+        log.extend_from_slice(&[0; 4096]);
+        let (recovered, report) = recover(&log);
+        assert_eq!(recovered, LOG);
+        assert_eq!(
+            (report.kept, report.dropped, report.zero_bytes),
+            (2, 1, 4096)
+        );
+    }
+
+    #[test]
+    fn drops_records_that_dont_fit_the_header() {
+        // Too few and too many columns, and a garbled timestamp:
+        let log = format!("{}300,S\n400,S,1.7,2\nx00,S,1.8\n500,S,1.9\r\n", LOG);
+        let (recovered, report) = recover(log.as_bytes());
+        assert_eq!(recovered, format!("{}500,S,1.9\n", LOG));
+        assert_eq!((report.kept, report.dropped), (3, 3));
+    }
+
+    #[test]
+    fn keeps_a_log_cut_off_in_the_header() {
+        let (recovered, report) = recover(b"# version: 1.0\ntimestamp,sta");
+        assert_eq!(recovered, "# version: 1.0\n");
+        assert_eq!((report.kept, report.dropped), (0, 1));
+    }
+}
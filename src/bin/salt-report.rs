@@ -0,0 +1,831 @@
+//! Generates a post-flight report from a flight log: apogee, maximum velocity and acceleration,
+//! burn and coast times, descent rates, state transition times, sensor error counts and telemetry
+//! link stats, as Markdown and JSON, plus SVG plots of altitude, velocity and acceleration.
+//!
+//! Usage: `salt-report <log.csv|log.bin>`. The report is written next to the log as
+//! `<log>_report.md`, `<log>_report.json` and `<log>_<quantity>.svg`. The event log
+//! (`<log>_events.csv`) and flight summary (`<log>_summary.txt`) of the same flight are used for
+//! the sensor errors and link stats, if they exist.
+
+use salt::binlog::BinaryLog;
+use salt::state::{StateId, state_letter};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+/// Maximum number of points drawn in a plot, so the SVGs stay small:
+const MAX_PLOT_POINTS: usize = 2000;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <log.csv|log.bin>", args[0]);
+        process::exit(1);
+    }
+    let input_path = &args[1];
+    let base_path = input_path
+        .strip_suffix(".csv")
+        .or_else(|| input_path.strip_suffix(".bin"))
+        .unwrap_or(input_path);
+
+    let log = match FlightLog::open(input_path) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input_path, e);
+            process::exit(1);
+        }
+    };
+    let flight = match Flight::from_log(&log) {
+        Some(flight) => flight,
+        None => {
+            eprintln!(
+                "{} has no records with a timestamp, state and altitude.",
+                input_path
+            );
+            process::exit(1);
+        }
+    };
+    if flight.skipped_records > 0 {
+        eprintln!(
+            "Skipped {} records without a valid timestamp.",
+            flight.skipped_records
+        );
+    }
+
+    let report = Report::new(
+        &flight,
+        fs::read_to_string(format!("{}_events.csv", base_path))
+            .ok()
+            .as_deref(),
+        fs::read_to_string(format!("{}_summary.txt", base_path))
+            .ok()
+            .as_deref(),
+    );
+
+    let mut plots = Vec::new();
+    let quantities = [
+        ("altitude", "Altitude", "m", &flight.altitude),
+        ("velocity", "Vertical velocity", "m/s", &flight.velocity),
+        ("acceleration", "Acceleration", "m/s²", &flight.acceleration),
+    ];
+    for (name, title, unit, values) in quantities {
+        let path = format!("{}_{}.svg", base_path, name);
+        match fs::write(&path, plot_svg(title, unit, &flight, values)) {
+            Ok(()) => plots.push((title, path)),
+            Err(e) => eprintln!("Failed to write {}: {}", path, e),
+        }
+    }
+
+    let markdown = report.to_markdown(input_path, &plots);
+    let outputs = [
+        (format!("{}_report.md", base_path), markdown.clone()),
+        (format!("{}_report.json", base_path), report.to_json()),
+    ];
+    for (path, contents) in outputs {
+        if let Err(e) = fs::write(&path, contents) {
+            eprintln!("Failed to write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    println!("{}", markdown);
+}
+
+/// The columns and records of a csv or binary log, as text.
+struct FlightLog {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl FlightLog {
+    fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        if path.ends_with(".bin") {
+            let log = BinaryLog::open(path)?;
+            return Ok(FlightLog {
+                columns: log.fields.iter().map(|field| field.name.clone()).collect(),
+                rows: log
+                    .records
+                    .iter()
+                    .map(|record| record.iter().map(|value| value.to_string()).collect())
+                    .collect(),
+            });
+        }
+
+        FlightLog::from_csv(&fs::read_to_string(path)?)
+    }
+
+    fn from_csv(text: &str) -> Result<Self, Box<dyn Error>> {
+        // Skip the metadata comments before the header:
+        let mut lines = text.lines().filter(|line| !line.starts_with('#'));
+        let columns: Vec<String> = lines
+            .next()
+            .ok_or("the log is empty")?
+            .split(',')
+            .map(String::from)
+            .collect();
+        let rows = lines
+            .map(|line| line.split(',').map(String::from).collect::<Vec<String>>())
+            .filter(|row| row.len() == columns.len())
+            .collect();
+        Ok(FlightLog { columns, rows })
+    }
+
+    /// The values of a column, with NaN for empty or invalid cells.
+    fn values(&self, name: &str) -> Option<Vec<f64>> {
+        let index = self.columns.iter().position(|column| column == name)?;
+        Some(
+            self.rows
+                .iter()
+                .map(|row| row[index].parse().unwrap_or(f64::NAN))
+                .collect(),
+        )
+    }
+}
+
+/// The logged quantities the report is based on, one entry per record.
+struct Flight {
+    /// Seconds since launch, or since the start of the log if no launch was detected:
+    time: Vec<f64>,
+    states: Vec<char>,
+    altitude: Vec<f64>,
+    velocity: Vec<f64>,
+    /// Magnitude of the (blended, if there is a high-g accelerometer) acceleration:
+    acceleration: Vec<f64>,
+    /// Records left out because their timestamp isn't a whole number of nanoseconds:
+    skipped_records: usize,
+}
+
+impl Flight {
+    fn from_log(log: &FlightLog) -> Option<Self> {
+        let timestamp_index = log
+            .columns
+            .iter()
+            .position(|column| column == "timestamp")?;
+        let state_index = log.columns.iter().position(|column| column == "state")?;
+        // A record without a valid timestamp can't be placed in time, so it's left out of
+        // everything:
+        let parsed: Vec<Option<u64>> = log
+            .rows
+            .iter()
+            .map(|row| row[timestamp_index].parse().ok())
+            .collect();
+        let timestamps: Vec<u64> = parsed.iter().flatten().copied().collect();
+        let states: Vec<char> = log
+            .rows
+            .iter()
+            .map(|row| row[state_index].chars().next().unwrap_or('U'))
+            .collect();
+        let states = timestamped(states, &parsed);
+        let altitude = timestamped(log.values("altitude")?, &parsed);
+        let velocity = log.values("velocity").map_or_else(
+            || vec![f64::NAN; altitude.len()],
+            |velocity| timestamped(velocity, &parsed),
+        );
+        if timestamps.is_empty() {
+            return None;
+        }
+
+        // Prefer the blended acceleration, which uses the high-g accelerometer once the main one
+        // saturates:
+        let axes = |prefix: &str| -> Option<Vec<Vec<f64>>> {
+            ["x", "y", "z"]
+                .iter()
+                .map(|axis| log.values(&format!("{}_{}", prefix, axis)))
+                .collect()
+        };
+        let magnitude = |axes: &Vec<Vec<f64>>, i: usize| {
+            (axes[0][i].powi(2) + axes[1][i].powi(2) + axes[2][i].powi(2)).sqrt()
+        };
+        let blended = axes("blended_accel");
+        let raw = axes("accel");
+        let acceleration = (0..log.rows.len())
+            .map(|i| {
+                let blended = blended.as_ref().map_or(f64::NAN, |axes| magnitude(axes, i));
+                if blended.is_nan() {
+                    raw.as_ref().map_or(f64::NAN, |axes| magnitude(axes, i))
+                } else {
+                    blended
+                }
+            })
+            .collect();
+        let acceleration = timestamped(acceleration, &parsed);
+
+        let launch_index = states.iter().position(|&state| state == 'M').unwrap_or(0);
+        let zero = timestamps[launch_index];
+        let time = timestamps
+            .iter()
+            .map(|&timestamp| (timestamp as i128 - zero as i128) as f64 / 1e9)
+            .collect();
+
+        Some(Flight {
+            time,
+            states,
+            altitude,
+            velocity,
+            acceleration,
+            skipped_records: parsed.len() - timestamps.len(),
+        })
+    }
+
+    /// Consecutive runs of the same state, as (state, first index, last index).
+    fn spans(&self) -> Vec<(char, usize, usize)> {
+        let mut spans: Vec<(char, usize, usize)> = Vec::new();
+        for (i, &state) in self.states.iter().enumerate() {
+            match spans.last_mut() {
+                Some((current, _, end)) if *current == state => *end = i,
+                _ => spans.push((state, i, i)),
+            }
+        }
+        spans
+    }
+
+    /// Seconds spent in `state`, from entering it until the next state.
+    fn time_in_state(&self, state: char) -> Option<f64> {
+        let spans = self.spans();
+        let mut total = None;
+        for (i, &(span_state, start, end)) in spans.iter().enumerate() {
+            if span_state == state {
+                let end_time = match spans.get(i + 1) {
+                    Some(&(_, next_start, _)) => self.time[next_start],
+                    None => self.time[end],
+                };
+                *total.get_or_insert(0.0) += end_time - self.time[start];
+            }
+        }
+        total
+    }
+}
+
+/// `values` without the ones from records whose timestamp didn't parse.
+fn timestamped<T>(values: Vec<T>, timestamps: &[Option<u64>]) -> Vec<T> {
+    values
+        .into_iter()
+        .zip(timestamps)
+        .filter_map(|(value, timestamp)| timestamp.map(|_| value))
+        .collect()
+}
+
+/// Name of the state logged as `letter`.
+fn state_name(letter: char) -> &'static str {
+    match StateId::ALL
+        .iter()
+        .find(|state| state_letter(state.name()) == letter)
+    {
+        Some(state) => state.name(),
+        // Logs from before the descent was split into drogue and main:
+        None if letter == 'F' => "FreeFall",
+        None => "Unknown",
+    }
+}
+
+/// Index and value of the largest non-NaN value.
+fn max_with_index(values: &[f64]) -> Option<(usize, f64)> {
+    values
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(_, value)| !value.is_nan())
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+struct Report {
+    duration: f64,
+    launch_detected: bool,
+    /// (value, time) pairs:
+    apogee: Option<(f64, f64)>,
+    max_velocity: Option<(f64, f64)>,
+    max_acceleration: Option<(f64, f64)>,
+    burn_time: Option<f64>,
+    coast_time: Option<f64>,
+    /// Average descent rate in each state after apogee, as (state, m/s):
+    descent_rates: Vec<(&'static str, f64)>,
+    /// (time, from, to):
+    transitions: Vec<(f64, &'static str, &'static str)>,
+    /// Number of times each sensor started failing, from the event log:
+    sensor_errors: Option<BTreeMap<String, u32>>,
+    /// The `telemetry_*` lines of the flight summary:
+    link_stats: Option<Vec<(String, String)>>,
+}
+
+impl Report {
+    fn new(flight: &Flight, events: Option<&str>, summary: Option<&str>) -> Self {
+        let at = |found: Option<(usize, f64)>| found.map(|(i, value)| (value, flight.time[i]));
+        let apogee = max_with_index(&flight.altitude);
+
+        let spans = flight.spans();
+        let transitions = spans
+            .windows(2)
+            .map(|pair| {
+                (
+                    flight.time[pair[1].1],
+                    state_name(pair[0].0),
+                    state_name(pair[1].0),
+                )
+            })
+            .collect();
+
+        let mut descent_rates = Vec::new();
+        if let Some((apogee_index, _)) = apogee {
+            for (i, &(state, start, end)) in spans.iter().enumerate() {
+                // Measure until the next state starts, so short states still have a duration:
+                let end = spans
+                    .get(i + 1)
+                    .map_or(end, |&(_, next_start, _)| next_start);
+                let (start, end) = (start.max(apogee_index), end);
+                if end <= start || matches!(state, 'L' | 'R' | 'X') {
+                    continue;
+                }
+                let duration = flight.time[end] - flight.time[start];
+                let drop = flight.altitude[start] - flight.altitude[end];
+                if duration > 0.0 && !drop.is_nan() {
+                    descent_rates.push((state_name(state), drop / duration));
+                }
+            }
+        }
+
+        let sensor_errors = events.map(|events| {
+            let mut counts = BTreeMap::new();
+            // Lines are `timestamp,severity,kind,"payload"`, with payloads like
+            // "BMP280 pressure: ...":
+            for line in events.lines().skip(1) {
+                let parts: Vec<&str> = line.splitn(4, ',').collect();
+                if parts.len() == 4 && parts[2] == "sensor_error" {
+                    let payload = parts[3].trim_matches('"');
+                    let sensor = payload.split(':').next().unwrap_or(payload);
+                    *counts.entry(sensor.to_string()).or_insert(0) += 1;
+                }
+            }
+            counts
+        });
+
+        let link_stats = summary.map(|summary| {
+            summary
+                .lines()
+                .filter_map(|line| line.split_once(": "))
+                .filter(|(key, _)| key.starts_with("telemetry_"))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        });
+
+        Report {
+            duration: flight.time[flight.time.len() - 1] - flight.time[0],
+            launch_detected: flight.states.contains(&'M'),
+            apogee: at(apogee),
+            max_velocity: at(max_with_index(&flight.velocity)),
+            max_acceleration: at(max_with_index(&flight.acceleration)),
+            burn_time: flight.time_in_state('M'),
+            coast_time: flight.time_in_state('O'),
+            descent_rates,
+            transitions,
+            sensor_errors,
+            link_stats,
+        }
+    }
+
+    fn to_markdown(&self, log_path: &str, plots: &[(&str, String)]) -> String {
+        let value_at = |found: Option<(f64, f64)>, unit: &str| match found {
+            Some((value, time)) => format!("{:.1} {} at T{:+.2} s", value, unit, time),
+            None => String::from("n/a"),
+        };
+        let seconds =
+            |time: Option<f64>| time.map_or(String::from("n/a"), |time| format!("{:.2} s", time));
+
+        let mut out = String::new();
+        let _ = writeln!(out, "# Flight report: {}\n", log_path);
+        if !self.launch_detected {
+            let _ = writeln!(
+                out,
+                "No launch was detected, times are from the start of the log.\n"
+            );
+        }
+        let _ = writeln!(out, "| | |\n|---|---|");
+        let _ = writeln!(out, "| Log duration | {:.1} s |", self.duration);
+        let _ = writeln!(out, "| Apogee | {} |", value_at(self.apogee, "m"));
+        let _ = writeln!(
+            out,
+            "| Max velocity | {} |",
+            value_at(self.max_velocity, "m/s")
+        );
+        let _ = writeln!(
+            out,
+            "| Max acceleration | {} |",
+            value_at(self.max_acceleration, "m/s²")
+        );
+        let _ = writeln!(out, "| Burn time | {} |", seconds(self.burn_time));
+        let _ = writeln!(out, "| Coast time | {} |", seconds(self.coast_time));
+
+        let _ = writeln!(out, "\n## Descent rates\n");
+        if self.descent_rates.is_empty() {
+            let _ = writeln!(out, "No descent after apogee in the log.");
+        } else {
+            let _ = writeln!(out, "| State | Descent rate |\n|---|---|");
+            for (state, rate) in &self.descent_rates {
+                let _ = writeln!(out, "| {} | {:.1} m/s |", state, rate);
+            }
+        }
+
+        let _ = writeln!(out, "\n## State transitions\n");
+        let _ = writeln!(out, "| Time | From | To |\n|---|---|---|");
+        for (time, from, to) in &self.transitions {
+            let _ = writeln!(out, "| T{:+.2} s | {} | {} |", time, from, to);
+        }
+
+        let _ = writeln!(out, "\n## Sensor errors\n");
+        match &self.sensor_errors {
+            None => {
+                let _ = writeln!(out, "No event log found.");
+            }
+            Some(counts) if counts.is_empty() => {
+                let _ = writeln!(out, "None.");
+            }
+            Some(counts) => {
+                let _ = writeln!(out, "| Sensor | Times it started failing |\n|---|---|");
+                for (sensor, count) in counts {
+                    let _ = writeln!(out, "| {} | {} |", sensor, count);
+                }
+            }
+        }
+
+        let _ = writeln!(out, "\n## Telemetry link\n");
+        match &self.link_stats {
+            Some(stats) if !stats.is_empty() => {
+                let _ = writeln!(out, "| | |\n|---|---|");
+                for (key, value) in stats {
+                    let _ = writeln!(
+                        out,
+                        "| {} | {} |",
+                        key.trim_start_matches("telemetry_"),
+                        value
+                    );
+                }
+            }
+            _ => {
+                let _ = writeln!(out, "No link stats in the flight summary.");
+            }
+        }
+
+        if !plots.is_empty() {
+            let _ = writeln!(out, "\n## Plots\n");
+            for (title, path) in plots {
+                let file_name = Path::new(path)
+                    .file_name()
+                    .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+                let _ = writeln!(out, "![{}]({})", title, file_name);
+            }
+        }
+        out
+    }
+
+    fn to_json(&self) -> String {
+        let value_at = |found: Option<(f64, f64)>| match found {
+            Some((value, time)) => format!(
+                "{{\"value\": {}, \"time_s\": {}}}",
+                json_number(value),
+                json_number(time)
+            ),
+            None => String::from("null"),
+        };
+        let optional = |value: Option<f64>| value.map_or(String::from("null"), json_number);
+
+        let descent_rates: Vec<String> = self
+            .descent_rates
+            .iter()
+            .map(|(state, rate)| {
+                format!(
+                    "{{\"state\": {}, \"rate_m_per_s\": {}}}",
+                    json_string(state),
+                    json_number(*rate)
+                )
+            })
+            .collect();
+        let transitions: Vec<String> = self
+            .transitions
+            .iter()
+            .map(|(time, from, to)| {
+                format!(
+                    "{{\"time_s\": {}, \"from\": {}, \"to\": {}}}",
+                    json_number(*time),
+                    json_string(from),
+                    json_string(to)
+                )
+            })
+            .collect();
+        let sensor_errors = match &self.sensor_errors {
+            Some(counts) => {
+                let entries: Vec<String> = counts
+                    .iter()
+                    .map(|(sensor, count)| format!("{}: {}", json_string(sensor), count))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            None => String::from("null"),
+        };
+        let link_stats = match &self.link_stats {
+            Some(stats) => {
+                let entries: Vec<String> = stats
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = key.trim_start_matches("telemetry_");
+                        match value.parse::<f64>() {
+                            Ok(number) => format!("{}: {}", json_string(key), json_number(number)),
+                            Err(_) => format!("{}: {}", json_string(key), json_string(value)),
+                        }
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            None => String::from("null"),
+        };
+
+        format!(
+            "{{\n  \"duration_s\": {},\n  \"launch_detected\": {},\n  \"apogee_m\": {},\n  \
+             \"max_velocity_m_per_s\": {},\n  \"max_acceleration_m_per_s2\": {},\n  \
+             \"burn_time_s\": {},\n  \"coast_time_s\": {},\n  \"descent_rates\": [{}],\n  \
+             \"transitions\": [{}],\n  \"sensor_errors\": {},\n  \"telemetry\": {}\n}}\n",
+            json_number(self.duration),
+            self.launch_detected,
+            value_at(self.apogee),
+            value_at(self.max_velocity),
+            value_at(self.max_acceleration),
+            optional(self.burn_time),
+            optional(self.coast_time),
+            descent_rates.join(", "),
+            transitions.join(", "),
+            sensor_errors,
+            link_stats
+        )
+    }
+}
+
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        format!("{}", value)
+    } else {
+        String::from("null")
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A line plot of `values` against time, with the state transitions marked.
+fn plot_svg(title: &str, unit: &str, flight: &Flight, values: &[f64]) -> String {
+    const WIDTH: f64 = 800.0;
+    const HEIGHT: f64 = 400.0;
+    const LEFT: f64 = 70.0;
+    const RIGHT: f64 = 20.0;
+    const TOP: f64 = 40.0;
+    const BOTTOM: f64 = 50.0;
+
+    let (t_min, t_max) = (flight.time[0], flight.time[flight.time.len() - 1]);
+    let finite = values.iter().copied().filter(|value| value.is_finite());
+    let (mut y_min, mut y_max) = finite
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+    if !y_min.is_finite() {
+        (y_min, y_max) = (0.0, 1.0);
+    }
+    if y_max - y_min < 1e-9 {
+        y_max = y_min + 1.0;
+    }
+    let t_span = (t_max - t_min).max(1e-9);
+    let x = |t: f64| LEFT + (t - t_min) / t_span * (WIDTH - LEFT - RIGHT);
+    let y = |value: f64| TOP + (y_max - value) / (y_max - y_min) * (HEIGHT - TOP - BOTTOM);
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">",
+        WIDTH, HEIGHT
+    );
+    let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"24\" text-anchor=\"middle\" font-size=\"16\">{} ({})</text>",
+        WIDTH / 2.0,
+        title,
+        unit
+    );
+
+    // Axes and ticks:
+    for i in 0..=5 {
+        let value = y_min + (y_max - y_min) * i as f64 / 5.0;
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{l}\" x2=\"{r}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"#ddd\"/><text x=\"{tx}\" y=\"{ty:.1}\" text-anchor=\"end\">{v:.1}</text>",
+            l = LEFT,
+            r = WIDTH - RIGHT,
+            y = y(value),
+            tx = LEFT - 6.0,
+            ty = y(value) + 4.0,
+            v = value
+        );
+        let time = t_min + t_span * i as f64 / 5.0;
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{:.1}</text>",
+            x(time),
+            HEIGHT - BOTTOM + 18.0,
+            time
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Time (s)</text>",
+        WIDTH / 2.0,
+        HEIGHT - 10.0
+    );
+    let _ = writeln!(
+        svg,
+        "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>",
+        LEFT,
+        TOP,
+        WIDTH - LEFT - RIGHT,
+        HEIGHT - TOP - BOTTOM
+    );
+
+    // State transitions:
+    let spans = flight.spans();
+    for &(state, start, _) in spans.iter().skip(1) {
+        let transition_x = x(flight.time[start]);
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{x:.1}\" x2=\"{x:.1}\" y1=\"{}\" y2=\"{}\" stroke=\"#c33\" stroke-dasharray=\"4 3\"/><text x=\"{:.1}\" y=\"{}\" fill=\"#c33\">{}</text>",
+            TOP,
+            HEIGHT - BOTTOM,
+            transition_x + 3.0,
+            TOP + 12.0,
+            state_name(state),
+            x = transition_x
+        );
+    }
+
+    // The data, with gaps where values are missing:
+    let step = values.len().div_ceil(MAX_PLOT_POINTS).max(1);
+    let mut path = String::new();
+    let mut pen_down = false;
+    for i in (0..values.len()).step_by(step) {
+        if values[i].is_finite() {
+            let _ = write!(
+                path,
+                "{}{:.1},{:.1} ",
+                if pen_down { "L" } else { "M" },
+                x(flight.time[i]),
+                y(values[i])
+            );
+            pen_down = true;
+        } else {
+            pen_down = false;
+        }
+    }
+    let _ = writeln!(
+        svg,
+        "<path d=\"{}\" fill=\"none\" stroke=\"#1f5fbf\" stroke-width=\"1.5\"/>",
+        path.trim_end()
+    );
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A flight from `rows` of (seconds, state, altitude).
+    fn flight_from(rows: &[(f64, char, f64)]) -> Flight {
+        let mut csv = String::from("# version: 1.0\ntimestamp,state,altitude\n");
+        for (seconds, state, altitude) in rows {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                (seconds * 1e9) as u64,
+                state,
+                altitude
+            ));
+        }
+        Flight::from_log(&FlightLog::from_csv(&csv).unwrap()).unwrap()
+    }
+
+    /// Launch at 2 s, apogee of 200 m at 5 s, drogue at 6 s, main at 9 s, landed at 20 s.
+    fn full_flight() -> Flight {
+        flight_from(&[
+            (0.0, 'S', 0.0),
+            (1.0, 'C', 0.0),
+            (2.0, 'M', 0.0),
+            (3.0, 'M', 50.0),
+            (4.0, 'O', 150.0),
+            (5.0, 'O', 200.0),
+            (6.0, 'D', 190.0),
+            (8.0, 'D', 170.0),
+            (9.0, 'N', 160.0),
+            (19.0, 'N', 110.0),
+            (20.0, 'L', 100.0),
+            (21.0, 'L', 100.0),
+        ])
+    }
+
+    #[test]
+    fn time_starts_at_launch() {
+        let flight = full_flight();
+        assert_eq!(flight.time[0], -2.0);
+        assert_eq!(flight.time[2], 0.0);
+        assert_eq!(flight.time[11], 19.0);
+        assert_eq!(flight.states[4], 'O');
+        assert_eq!(flight.altitude[5], 200.0);
+        assert!(flight.velocity.iter().all(|velocity| velocity.is_nan()));
+    }
+
+    #[test]
+    fn time_starts_with_the_log_without_a_launch() {
+        let flight = flight_from(&[(10.0, 'S', 0.0), (11.0, 'C', 0.0), (12.5, 'A', 0.0)]);
+        assert_eq!(flight.time, vec![0.0, 1.0, 2.5]);
+    }
+
+    #[test]
+    fn skips_records_without_a_valid_timestamp() {
+        let csv = "timestamp,state,altitude\n1000000000,S,1\n1.5e9,C,2\n,C,3\n3000000000,M,4\n";
+        let flight = Flight::from_log(&FlightLog::from_csv(csv).unwrap()).unwrap();
+        assert_eq!(flight.skipped_records, 2);
+        assert_eq!(flight.time, vec![-2.0, 0.0]);
+        assert_eq!(flight.states, vec!['S', 'M']);
+        assert_eq!(flight.altitude, vec![1.0, 4.0]);
+        assert_eq!(flight.acceleration.len(), 2);
+
+        let csv = "timestamp,state,altitude\n1.5e9,C,2\n";
+        assert!(Flight::from_log(&FlightLog::from_csv(csv).unwrap()).is_none());
+    }
+
+    #[test]
+    fn needs_a_timestamp_state_and_altitude() {
+        let csv = "timestamp,state\n1000000000,S\n";
+        assert!(Flight::from_log(&FlightLog::from_csv(csv).unwrap()).is_none());
+    }
+
+    #[test]
+    fn time_in_state() {
+        let flight = full_flight();
+        // Until the next state starts, not the last record of the state:
+        assert_eq!(flight.time_in_state('M'), Some(2.0));
+        assert_eq!(flight.time_in_state('O'), Some(2.0));
+        // The last state lasts until the end of the log:
+        assert_eq!(flight.time_in_state('L'), Some(1.0));
+        assert_eq!(flight.time_in_state('A'), None);
+
+        // Every time the state was entered counts:
+        let repeated = flight_from(&[
+            (0.0, 'S', 0.0),
+            (1.0, 'C', 0.0),
+            (3.0, 'S', 0.0),
+            (4.0, 'C', 0.0),
+        ]);
+        assert_eq!(repeated.time_in_state('S'), Some(2.0));
+        assert_eq!(repeated.time_in_state('C'), Some(2.0));
+    }
+
+    #[test]
+    fn descent_rates_after_apogee() {
+        let report = Report::new(&full_flight(), None, None);
+        assert_eq!(report.apogee, Some((200.0, 3.0)));
+        assert_eq!(report.burn_time, Some(2.0));
+        assert_eq!(report.coast_time, Some(2.0));
+        let rates = &report.descent_rates;
+        assert_eq!(rates.len(), 3, "{:?}", rates);
+        // The coast only counts from apogee:
+        assert_eq!(rates[0], ("Coast", 10.0));
+        assert_eq!(rates[1], ("DrogueDescent", 10.0));
+        assert_eq!(rates[2].0, "MainDescent");
+        assert!((rates[2].1 - 60.0 / 11.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(json_number(1.5), "1.5");
+        assert_eq!(json_number(-3.0), "-3");
+        assert_eq!(json_number(f64::NAN), "null");
+        assert_eq!(json_number(f64::INFINITY), "null");
+
+        assert_eq!(json_string("BMP280"), "\"BMP280\"");
+        assert_eq!(json_string("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(
+            json_string("line\nbreak\u{7}"),
+            "\"line\\u000abreak\\u0007\""
+        );
+        assert_eq!(json_string("m/s²"), "\"m/s²\"");
+    }
+}
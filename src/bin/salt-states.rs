@@ -0,0 +1,29 @@
+//! Prints the state machine's transition table as a Mermaid diagram, and checks that every state
+//! can be reached from Standby. The debounce shown is the one that would be flown with the flight
+//! config, including its `[debounce.<guard>]` overrides.
+//!
+//! Usage: `salt-states [flight_config.toml]`. Exits with an error if any state is unreachable.
+
+use salt::config::{CONFIG_FILE_PATH, FlightConfig};
+use salt::transitions::{Debouncer, diagram, unreachable_states};
+use std::env;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let config = match FlightConfig::load(args.get(1).map_or(CONFIG_FILE_PATH, String::as_str)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load flight config {}", e);
+            process::exit(1);
+        }
+    };
+    print!("{}", diagram(&Debouncer::new(&config.debounce)));
+
+    let unreachable = unreachable_states();
+    if !unreachable.is_empty() {
+        let names: Vec<&str> = unreachable.iter().map(|state| state.name()).collect();
+        eprintln!("Unreachable states: {}", names.join(", "));
+        process::exit(1);
+    }
+}
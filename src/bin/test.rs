@@ -1,4 +1,6 @@
 use bmp280::Bmp280Builder;
+use linux_embedded_hal::I2cdev;
+use salt::bno055::Bno055;
 use std::io::Write;
 use std::process::{Command, Stdio};
 use std::thread;
@@ -78,6 +80,7 @@ fn transmit_data() {
 
 fn run_sensors() {
     let mut bmp280 = init_pressure_sensor();
+    let mut bno055 = init_orientation_sensor();
     let mut transmitted_packet = TransmittedPacket {
         pressure: 0.0,
         altitude: 0.0,
@@ -110,7 +113,14 @@ fn run_sensors() {
             println!("Read/write error");
         }
 
-        // Orientation sensor removed (BNO055 crate not included). Orientation fields left at defaults.
+        // Orientation
+        if let Some(sensor) = bno055.as_mut() {
+            if let Ok((heading, roll, pitch)) = sensor.read_euler() {
+                println!("roll {:?}°, pitch {:?}°, yaw {:?}°", roll, pitch, heading);
+            } else {
+                println!("Read/write error");
+            }
+        }
 
 
         std::thread::sleep(std::time::Duration::from_millis(50));
@@ -130,4 +140,13 @@ fn init_pressure_sensor() -> bmp280::Bmp280 {
     return bmp280;
 }
 
-// Orientation sensor initialization removed.
+fn init_orientation_sensor() -> Option<Bno055<I2cdev>> {
+    let i2cdev = I2cdev::new("/dev/i2c-1").expect("Failed to open /dev/i2c-1");
+    match Bno055::new(i2cdev, 0x28, true) {
+        Ok(sensor) => Some(sensor),
+        Err(e) => {
+            eprintln!("BNO055 init failed: {}", e);
+            None
+        }
+    }
+}
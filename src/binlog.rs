@@ -0,0 +1,488 @@
+//! Compact binary flight log format, and a reader for it.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! header:  "SALTLOG\0" | version: u16 | field count: u16
+//!          | per field: kind: u8, name length: u8, name (UTF-8)
+//!          | metadata count: u16 (since version 2)
+//!          | per entry: key length: u8, key, value length: u32, value (UTF-8)
+//!          | CRC-32 of everything above: u32
+//! block:   "SBLK" | record count: u32 | CRC-32 of the record count and records: u32 | records
+//!          (before version 3, the CRC only covered the records)
+//! record:  every field in header order, at its fixed size (see `FieldKind::size`)
+//! ```
+//!
+//! Records are grouped into blocks so each block can be checked on its own. A block cut short by
+//! a power loss, or with a bad CRC or record count, is skipped without losing the blocks around
+//! it.
+
+use crate::log_schema::{FieldKind, LogField, LogValue};
+use std::fs;
+use std::io::{self, Write};
+
+pub const FILE_MAGIC: &[u8; 8] = b"SALTLOG\0";
+pub const FORMAT_VERSION: u16 = 3;
+const BLOCK_MAGIC: &[u8; 4] = b"SBLK";
+const BLOCK_HEADER_SIZE: usize = 12;
+/// Blocks are also written whenever the log is flushed, so they are usually much smaller:
+const MAX_RECORDS_PER_BLOCK: u32 = 256;
+
+fn kind_to_byte(kind: FieldKind) -> u8 {
+    match kind {
+        FieldKind::U64 => 0,
+        FieldKind::Char => 1,
+        FieldKind::Bool => 2,
+        FieldKind::U8 => 3,
+        FieldKind::F32 => 4,
+        FieldKind::F64 => 5,
+    }
+}
+
+fn kind_from_byte(byte: u8) -> Option<FieldKind> {
+    match byte {
+        0 => Some(FieldKind::U64),
+        1 => Some(FieldKind::Char),
+        2 => Some(FieldKind::Bool),
+        3 => Some(FieldKind::U8),
+        4 => Some(FieldKind::F32),
+        5 => Some(FieldKind::F64),
+        _ => None,
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub struct BinaryLogWriter<W: Write> {
+    writer: W,
+    block: Vec<u8>,
+    records_in_block: u32,
+}
+
+impl<W: Write> BinaryLogWriter<W> {
+    /// Writes the file header describing `fields`, followed by the flight `metadata`.
+    pub fn new(
+        mut writer: W,
+        fields: &[LogField],
+        metadata: &[(String, String)],
+    ) -> io::Result<Self> {
+        let mut header = Vec::new();
+        header.extend_from_slice(FILE_MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&(fields.len() as u16).to_le_bytes());
+        for field in fields {
+            let name = field.name.as_bytes();
+            if name.len() > u8::MAX as usize {
+                return Err(invalid_data("field name too long"));
+            }
+            header.push(kind_to_byte(field.kind));
+            header.push(name.len() as u8);
+            header.extend_from_slice(name);
+        }
+        header.extend_from_slice(&(metadata.len() as u16).to_le_bytes());
+        for (key, value) in metadata {
+            if key.len() > u8::MAX as usize {
+                return Err(invalid_data("metadata key too long"));
+            }
+            header.push(key.len() as u8);
+            header.extend_from_slice(key.as_bytes());
+            header.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            header.extend_from_slice(value.as_bytes());
+        }
+        let crc = crc32fast::hash(&header);
+        header.extend_from_slice(&crc.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(BinaryLogWriter {
+            writer,
+            block: Vec::new(),
+            records_in_block: 0,
+        })
+    }
+
+    /// Adds a record to the current block, writing the block out once it's full. The values must
+    /// match the fields given to `new`.
+    pub fn write_record(&mut self, values: &[LogValue]) -> io::Result<()> {
+        for value in values {
+            match *value {
+                LogValue::U64(v) => self.block.extend_from_slice(&v.to_le_bytes()),
+                LogValue::Char(c) => self.block.push(c as u8),
+                LogValue::Bool(b) => self.block.push(b as u8),
+                LogValue::U8(v) => self.block.push(v),
+                LogValue::F32(v) => self.block.extend_from_slice(&v.to_le_bytes()),
+                LogValue::F64(v) => self.block.extend_from_slice(&v.to_le_bytes()),
+            }
+        }
+        self.records_in_block += 1;
+        if self.records_in_block >= MAX_RECORDS_PER_BLOCK {
+            self.write_block()?;
+        }
+        Ok(())
+    }
+
+    /// Writes out the records collected so far as a block, and flushes the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.records_in_block == 0 {
+            return Ok(());
+        }
+        let crc = block_crc(FORMAT_VERSION, self.records_in_block, &self.block);
+        self.writer.write_all(BLOCK_MAGIC)?;
+        self.writer
+            .write_all(&self.records_in_block.to_le_bytes())?;
+        self.writer.write_all(&crc.to_le_bytes())?;
+        self.writer.write_all(&self.block)?;
+        self.block.clear();
+        self.records_in_block = 0;
+        Ok(())
+    }
+}
+
+/// A binary log read into memory.
+pub struct BinaryLog {
+    pub version: u16,
+    pub fields: Vec<LogField>,
+    pub metadata: Vec<(String, String)>,
+    pub records: Vec<Vec<LogValue>>,
+    /// Blocks skipped because their CRC or record count didn't match.
+    pub corrupt_blocks: usize,
+    /// Whether the log ended with an incomplete block, e.g. after a power loss.
+    pub truncated: bool,
+}
+
+impl BinaryLog {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let mut cursor = Cursor { bytes, position: 0 };
+
+        if cursor.take(FILE_MAGIC.len()) != Some(FILE_MAGIC) {
+            return Err(invalid_data("not a SALT binary log"));
+        }
+        let version = cursor
+            .u16()
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        if version > FORMAT_VERSION {
+            return Err(invalid_data("unsupported binary log version"));
+        }
+        let field_count = cursor
+            .u16()
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        let mut fields = Vec::with_capacity(field_count as usize);
+        for _ in 0..field_count {
+            let (Some(kind), Some(length)) = (cursor.u8(), cursor.u8()) else {
+                return Err(invalid_data("truncated header"));
+            };
+            let kind = kind_from_byte(kind).ok_or_else(|| invalid_data("unknown field kind"))?;
+            let name = cursor
+                .take(length as usize)
+                .ok_or_else(|| invalid_data("truncated header"))?;
+            fields.push(LogField {
+                name: String::from_utf8_lossy(name).into_owned(),
+                kind,
+            });
+        }
+        let mut metadata = Vec::new();
+        if version >= 2 {
+            let count = cursor
+                .u16()
+                .ok_or_else(|| invalid_data("truncated header"))?;
+            for _ in 0..count {
+                let key = cursor
+                    .u8()
+                    .and_then(|length| cursor.take(length as usize))
+                    .ok_or_else(|| invalid_data("truncated header"))?;
+                let value = cursor
+                    .u32()
+                    .and_then(|length| cursor.take(length as usize))
+                    .ok_or_else(|| invalid_data("truncated header"))?;
+                metadata.push((
+                    String::from_utf8_lossy(key).into_owned(),
+                    String::from_utf8_lossy(value).into_owned(),
+                ));
+            }
+        }
+        let header_end = cursor.position;
+        let header_crc = cursor
+            .u32()
+            .ok_or_else(|| invalid_data("truncated header"))?;
+        if crc32fast::hash(&bytes[..header_end]) != header_crc {
+            return Err(invalid_data("header CRC mismatch"));
+        }
+
+        let record_size: usize = fields.iter().map(|field| field.kind.size()).sum();
+        let mut log = BinaryLog {
+            version,
+            fields,
+            metadata,
+            records: Vec::new(),
+            corrupt_blocks: 0,
+            truncated: false,
+        };
+
+        let mut position = cursor.position;
+        while position < bytes.len() {
+            if bytes.len() - position < BLOCK_HEADER_SIZE {
+                log.truncated = true;
+                break;
+            }
+            if &bytes[position..position + 4] != BLOCK_MAGIC {
+                // Lost track of the blocks, look for the next one:
+                position += 1;
+                continue;
+            }
+            let count = read_u32(bytes, position + 4);
+            let crc = read_u32(bytes, position + 8);
+            let payload_start = position + BLOCK_HEADER_SIZE;
+            let payload_end =
+                payload_start.saturating_add((count as usize).saturating_mul(record_size));
+            if payload_end > bytes.len() {
+                // Either the last block was cut short, or the count isn't what was written. Only
+                // the first if there are no blocks after it:
+                match find_block(bytes, position + 1) {
+                    Some(next) => {
+                        log.corrupt_blocks += 1;
+                        position = next;
+                        continue;
+                    }
+                    None => {
+                        log.truncated = true;
+                        break;
+                    }
+                }
+            }
+            let payload = &bytes[payload_start..payload_end];
+            if block_crc(log.version, count, payload) != crc {
+                log.corrupt_blocks += 1;
+                position += 1;
+                continue;
+            }
+            for record in payload.chunks_exact(record_size.max(1)) {
+                log.records.push(decode_record(record, &log.fields));
+            }
+            position = payload_end;
+        }
+
+        Ok(log)
+    }
+}
+
+fn decode_record(bytes: &[u8], fields: &[LogField]) -> Vec<LogValue> {
+    let mut position = 0;
+    fields
+        .iter()
+        .map(|field| {
+            let value = &bytes[position..position + field.kind.size()];
+            position += field.kind.size();
+            match field.kind {
+                FieldKind::U64 => LogValue::U64(u64::from_le_bytes(value.try_into().unwrap())),
+                FieldKind::Char => LogValue::Char(value[0] as char),
+                FieldKind::Bool => LogValue::Bool(value[0] != 0),
+                FieldKind::U8 => LogValue::U8(value[0]),
+                FieldKind::F32 => LogValue::F32(f32::from_le_bytes(value.try_into().unwrap())),
+                FieldKind::F64 => LogValue::F64(f64::from_le_bytes(value.try_into().unwrap())),
+            }
+        })
+        .collect()
+}
+
+/// CRC of a block's records, and since version 3 of its record count, so a corrupt count can't
+/// pass for a shorter block.
+fn block_crc(version: u16, count: u32, records: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    if version >= 3 {
+        hasher.update(&count.to_le_bytes());
+    }
+    hasher.update(records);
+    hasher.finalize()
+}
+
+/// Position of the first block magic at or after `from`.
+fn find_block(bytes: &[u8], from: usize) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(BLOCK_MAGIC.len())
+        .position(|window| window == BLOCK_MAGIC)
+        .map(|offset| from + offset)
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap())
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.position..self.position + length)?;
+        self.position += length;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Vec<LogField> {
+        vec![
+            LogField {
+                name: String::from("timestamp"),
+                kind: FieldKind::U64,
+            },
+            LogField {
+                name: String::from("state"),
+                kind: FieldKind::Char,
+            },
+            LogField {
+                name: String::from("altitude"),
+                kind: FieldKind::F32,
+            },
+        ]
+    }
+
+    fn record(index: u64) -> Vec<LogValue> {
+        vec![
+            LogValue::U64(index),
+            LogValue::Char('S'),
+            LogValue::F32(index as f32 * 1.5),
+        ]
+    }
+
+    /// A log with `blocks` blocks of two records each, and where each block starts.
+    fn write_log(blocks: u64) -> (Vec<u8>, Vec<usize>) {
+        let metadata = vec![(String::from("version"), String::from("1.0"))];
+        let mut writer = BinaryLogWriter::new(Vec::new(), &fields(), &metadata).unwrap();
+        let mut block_starts = Vec::new();
+        for block in 0..blocks {
+            block_starts.push(writer.get_ref().len());
+            writer.write_record(&record(block * 2)).unwrap();
+            writer.write_record(&record(block * 2 + 1)).unwrap();
+            writer.flush().unwrap();
+        }
+        (writer.get_ref().clone(), block_starts)
+    }
+
+    fn timestamps(log: &BinaryLog) -> Vec<u64> {
+        log.records
+            .iter()
+            .map(|record| match record[0] {
+                LogValue::U64(timestamp) => timestamp,
+                _ => panic!("timestamp isn't a U64"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let (bytes, _) = write_log(3);
+        let log = BinaryLog::parse(&bytes).unwrap();
+        assert_eq!(log.version, FORMAT_VERSION);
+        assert_eq!(log.fields, fields());
+        assert_eq!(
+            log.metadata,
+            vec![(String::from("version"), String::from("1.0"))]
+        );
+        assert_eq!(log.records, (0..6).map(record).collect::<Vec<_>>());
+        assert_eq!(log.corrupt_blocks, 0);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn rejects_a_corrupt_header() {
+        let (mut bytes, _) = write_log(1);
+        bytes[FILE_MAGIC.len() + 5] ^= 1;
+        assert!(BinaryLog::parse(&bytes).is_err());
+        assert!(BinaryLog::parse(b"not a log").is_err());
+    }
+
+    #[test]
+    fn skips_a_block_with_a_bad_crc() {
+        let (mut bytes, block_starts) = write_log(3);
+        bytes[block_starts[1] + BLOCK_HEADER_SIZE + 2] ^= 1;
+        let log = BinaryLog::parse(&bytes).unwrap();
+        assert_eq!(timestamps(&log), vec![0, 1, 4, 5]);
+        assert_eq!(log.corrupt_blocks, 1);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn skips_a_block_with_a_bad_count() {
+        let (mut bytes, block_starts) = write_log(3);
+        bytes[block_starts[1] + 4 + 3] ^= 0x40;
+        let log = BinaryLog::parse(&bytes).unwrap();
+        assert_eq!(timestamps(&log), vec![0, 1, 4, 5]);
+        assert_eq!(log.corrupt_blocks, 1);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn skips_a_block_with_a_count_that_still_fits() {
+        // One record less still ends inside the log, so only the CRC can catch it:
+        let (mut bytes, block_starts) = write_log(3);
+        bytes[block_starts[1] + 4] = 1;
+        let log = BinaryLog::parse(&bytes).unwrap();
+        assert_eq!(timestamps(&log), vec![0, 1, 4, 5]);
+        assert_eq!(log.corrupt_blocks, 1);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn reads_version_2() {
+        // Version 2 had the same layout, with the block CRCs over the records only:
+        let (mut bytes, block_starts) = write_log(2);
+        bytes[FILE_MAGIC.len()..FILE_MAGIC.len() + 2].copy_from_slice(&2u16.to_le_bytes());
+        let header_end = block_starts[0] - 4;
+        let header_crc = crc32fast::hash(&bytes[..header_end]);
+        bytes[header_end..block_starts[0]].copy_from_slice(&header_crc.to_le_bytes());
+        let record_size = 8 + 1 + 4;
+        for start in block_starts {
+            let payload = start + BLOCK_HEADER_SIZE..start + BLOCK_HEADER_SIZE + 2 * record_size;
+            let crc = crc32fast::hash(&bytes[payload]);
+            bytes[start + 8..start + BLOCK_HEADER_SIZE].copy_from_slice(&crc.to_le_bytes());
+        }
+
+        let log = BinaryLog::parse(&bytes).unwrap();
+        assert_eq!(log.version, 2);
+        assert_eq!(log.records, (0..4).map(record).collect::<Vec<_>>());
+        assert_eq!(log.corrupt_blocks, 0);
+        assert!(!log.truncated);
+    }
+
+    #[test]
+    fn reports_a_truncated_last_block() {
+        let (bytes, _) = write_log(3);
+        let log = BinaryLog::parse(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(timestamps(&log), vec![0, 1, 2, 3]);
+        assert_eq!(log.corrupt_blocks, 0);
+        assert!(log.truncated);
+    }
+}
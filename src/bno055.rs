@@ -0,0 +1,167 @@
+//! Minimal driver for the Bosch BNO055, running in NDOF (9 degrees of freedom fusion) mode.
+
+use embedded_hal::i2c::I2c;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+const CHIP_ID: u8 = 0x00;
+const PAGE_ID: u8 = 0x07;
+const ACC_DATA_X_LSB: u8 = 0x08;
+const MAG_DATA_X_LSB: u8 = 0x0E;
+const GYR_DATA_X_LSB: u8 = 0x14;
+const EUL_DATA_X_LSB: u8 = 0x1A;
+const QUA_DATA_W_LSB: u8 = 0x20;
+const LIA_DATA_X_LSB: u8 = 0x28;
+const CALIB_STAT: u8 = 0x35;
+const UNIT_SEL: u8 = 0x3B;
+const OPR_MODE: u8 = 0x3D;
+const PWR_MODE: u8 = 0x3E;
+const SYS_TRIGGER: u8 = 0x3F;
+
+const EXPECTED_CHIP_ID: u8 = 0xA0;
+const OPR_MODE_CONFIG: u8 = 0x00;
+const OPR_MODE_NDOF: u8 = 0x0C;
+const PWR_MODE_NORMAL: u8 = 0x00;
+/// m/s^2 for acceleration, rad/s for the gyroscope, degrees for Euler angles, Celsius:
+const UNIT_SEL_RAD_PER_SECOND: u8 = 0x02;
+const SYS_TRIGGER_RESET: u8 = 0x20;
+const SYS_TRIGGER_EXTERNAL_CRYSTAL: u8 = 0x80;
+
+/// Number of registers from ACC_DATA_X_LSB up to and including CALIB_STAT:
+const DATA_BLOCK_LENGTH: usize = (CALIB_STAT - ACC_DATA_X_LSB + 1) as usize;
+
+/// Scale factors, in LSB per unit:
+const ACCEL_LSB_PER_METER_PER_SECOND_SQUARED: f32 = 100.0;
+const MAG_LSB_PER_MICROTESLA: f32 = 16.0;
+const GYRO_LSB_PER_RADIAN_PER_SECOND: f32 = 900.0;
+const EULER_LSB_PER_DEGREE: f32 = 16.0;
+const QUATERNION_LSB_PER_UNIT: f32 = 16384.0;
+
+/// Accelerometer full-scale range in g while running a fusion mode:
+pub const FUSION_ACCEL_RANGE_G: f32 = 4.0;
+/// Gyroscope full-scale range in degrees per second while running a fusion mode:
+pub const FUSION_GYRO_RANGE_DPS: f32 = 2000.0;
+
+/// One burst read of all the fused and raw sensor outputs.
+pub struct Bno055Reading {
+    pub acceleration: [f32; 3],        // m/s^2, including gravity
+    pub magnetic_field: [f32; 3],      // microteslas
+    pub gyro: [f32; 3],                // rad/s
+    pub quaternion: [f32; 4],          // (w, x, y, z)
+    pub linear_acceleration: [f32; 3], // m/s^2, gravity removed
+    pub calibration_status: [u8; 4],   // (system, gyro, accel, mag), each 0 (uncalibrated) to 3
+}
+
+pub struct Bno055<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Bno055<I>
+where
+    I::Error: Error + 'static,
+{
+    /// Resets the sensor and starts it in NDOF mode.
+    pub fn new(i2c: I, address: u8, external_crystal: bool) -> Result<Self, Box<dyn Error>> {
+        let mut sensor = Bno055 { i2c, address };
+        sensor.wait_for_chip_id()?;
+
+        sensor.write_register(OPR_MODE, OPR_MODE_CONFIG)?;
+        thread::sleep(Duration::from_millis(25));
+        sensor.write_register(SYS_TRIGGER, SYS_TRIGGER_RESET)?;
+        // The sensor doesn't respond while it resets, which takes about 650 ms:
+        thread::sleep(Duration::from_millis(650));
+        sensor.wait_for_chip_id()?;
+
+        sensor.write_register(PWR_MODE, PWR_MODE_NORMAL)?;
+        sensor.write_register(PAGE_ID, 0)?;
+        sensor.write_register(UNIT_SEL, UNIT_SEL_RAD_PER_SECOND)?;
+        let trigger = if external_crystal {
+            SYS_TRIGGER_EXTERNAL_CRYSTAL
+        } else {
+            0
+        };
+        sensor.write_register(SYS_TRIGGER, trigger)?;
+        thread::sleep(Duration::from_millis(10));
+
+        sensor.write_register(OPR_MODE, OPR_MODE_NDOF)?;
+        thread::sleep(Duration::from_millis(20));
+        Ok(sensor)
+    }
+
+    /// Reads every sensor output and the calibration status in a single transaction.
+    pub fn read(&mut self) -> Result<Bno055Reading, Box<dyn Error>> {
+        let mut buf = [0u8; DATA_BLOCK_LENGTH];
+        self.i2c
+            .write_read(self.address, &[ACC_DATA_X_LSB], &mut buf)?;
+
+        // Position of a register in the buffer:
+        let offset = |register: u8| (register - ACC_DATA_X_LSB) as usize;
+        let vector = |register: u8, scale: f32| {
+            [0, 1, 2].map(|axis| read_i16(&buf, offset(register) + axis * 2) as f32 / scale)
+        };
+        let calib_stat = buf[offset(CALIB_STAT)];
+
+        Ok(Bno055Reading {
+            acceleration: vector(ACC_DATA_X_LSB, ACCEL_LSB_PER_METER_PER_SECOND_SQUARED),
+            magnetic_field: vector(MAG_DATA_X_LSB, MAG_LSB_PER_MICROTESLA),
+            gyro: vector(GYR_DATA_X_LSB, GYRO_LSB_PER_RADIAN_PER_SECOND),
+            quaternion: [0, 1, 2, 3].map(|i| {
+                read_i16(&buf, offset(QUA_DATA_W_LSB) + i * 2) as f32 / QUATERNION_LSB_PER_UNIT
+            }),
+            linear_acceleration: vector(LIA_DATA_X_LSB, ACCEL_LSB_PER_METER_PER_SECOND_SQUARED),
+            calibration_status: [
+                (calib_stat >> 6) & 0x03,
+                (calib_stat >> 4) & 0x03,
+                (calib_stat >> 2) & 0x03,
+                calib_stat & 0x03,
+            ],
+        })
+    }
+
+    /// Reads the fused orientation as (heading, roll, pitch) in degrees.
+    pub fn read_euler(&mut self) -> Result<(f32, f32, f32), Box<dyn Error>> {
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(self.address, &[EUL_DATA_X_LSB], &mut buf)?;
+        Ok((
+            read_i16(&buf, 0) as f32 / EULER_LSB_PER_DEGREE,
+            read_i16(&buf, 2) as f32 / EULER_LSB_PER_DEGREE,
+            read_i16(&buf, 4) as f32 / EULER_LSB_PER_DEGREE,
+        ))
+    }
+
+    /// The BNO055 takes up to 850 ms to boot, so poll the chip ID for a while before giving up.
+    fn wait_for_chip_id(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut chip_id = 0;
+        for _ in 0..10 {
+            let mut id = [0u8];
+            if self
+                .i2c
+                .write_read(self.address, &[CHIP_ID], &mut id)
+                .is_ok()
+            {
+                chip_id = id[0];
+                if chip_id == EXPECTED_CHIP_ID {
+                    return Ok(());
+                }
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Err(format!(
+            "BNO055 chip ID mismatch at 0x{:02X}: 0x{:02X}",
+            self.address, chip_id
+        )
+        .into())
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Box<dyn Error>> {
+        self.i2c.write(self.address, &[register, value])?;
+        Ok(())
+    }
+}
+
+fn read_i16(buf: &[u8], offset: usize) -> i16 {
+    i16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
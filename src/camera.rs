@@ -0,0 +1,96 @@
+//! Records footage from the camera by running `rpicam-vid` in the background.
+
+use crate::config::CameraConfig;
+use crate::{info, warn};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for rpicam-vid to finish writing the video after asking it to stop:
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Camera {
+    config: CameraConfig,
+    recording: Option<Child>,
+}
+
+impl Camera {
+    pub fn new(config: &CameraConfig) -> Self {
+        Camera {
+            config: config.clone(),
+            recording: None,
+        }
+    }
+
+    /// Starts recording to `output_path` until `stop` is called.
+    pub fn start(&mut self, output_path: &str) {
+        if self.recording.is_some() {
+            return;
+        }
+        let result = Command::new("rpicam-vid")
+            .args([
+                "-t",
+                "0",
+                "-o",
+                output_path,
+                "--inline",
+                "--awb",
+                "auto",
+                "--width",
+                &self.config.width.to_string(),
+                "--height",
+                &self.config.height.to_string(),
+                "--framerate",
+                &self.config.framerate.to_string(),
+                "--nopreview",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        match result {
+            Ok(child) => {
+                info!("Camera recording to {}.", output_path);
+                self.recording = Some(child);
+            }
+            Err(e) => warn!("Failed to start camera recording: {}", e),
+        }
+    }
+
+    /// Stops the recording. rpicam-vid finishes writing the file when interrupted, so we send it
+    /// SIGINT and only kill it if it doesn't exit in time.
+    pub fn stop(&mut self) {
+        let Some(mut child) = self.recording.take() else {
+            return;
+        };
+        // SAFETY: `kill` has no memory safety requirements, and the pid belongs to our child,
+        // which hasn't been waited on yet.
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < STOP_TIMEOUT {
+            match child.try_wait() {
+                Ok(Some(_)) => {
+                    info!("Camera recording stopped.");
+                    return;
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                Err(e) => {
+                    warn!("Failed to wait for camera recording to stop: {}", e);
+                    break;
+                }
+            }
+        }
+        warn!("Camera recording didn't stop in time, killing it.");
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+impl Drop for Camera {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
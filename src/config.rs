@@ -0,0 +1,565 @@
+//! Flight configuration, loaded from a TOML file at startup.
+//!
+//! Every field has a default, so the file only needs to contain the values that differ from them.
+//! If the file is missing, the defaults are used; if it is invalid, the flight software refuses to
+//! start.
+
+use crate::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+
+/// Path of the flight configuration file, relative to the working directory:
+pub const CONFIG_FILE_PATH: &str = "flight_config.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FlightConfig {
+    pub imu: ImuConfig,
+    pub mach_lockout: MachLockoutConfig,
+    pub logging: LoggingConfig,
+    pub diagnostics: DiagnosticsConfig,
+    /// Optional GPS receiver, enabled by adding a `[gps]` section.
+    pub gps: Option<GpsConfig>,
+    /// Optional battery voltage monitor, enabled by adding a `[battery]` section.
+    pub battery: Option<BatteryConfig>,
+    /// Optional camera recording, enabled by adding a `[camera]` section.
+    pub camera: Option<CameraConfig>,
+    /// Optional pyro channels for deploying the parachutes, enabled by adding a `[pyro]` section.
+    pub pyro: Option<PyroConfig>,
+    pub deployment: DeploymentConfig,
+    pub safety: SafetyConfig,
+    /// Debounce overrides by guard, as `[debounce.<guard>]` sections. Guards without one use the
+    /// defaults from the transition table (see `transitions`).
+    pub debounce: BTreeMap<String, DebounceConfig>,
+    pub recovery: RecoveryConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+/// Settings for the inertial sensors.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ImuConfig {
+    /// Which sensor provides acceleration and rotation.
+    pub sensor: ImuSensor,
+    /// 7-bit I2C address of the BNO055 (0x28 or 0x29).
+    pub bno055_address: u8,
+    /// Whether the BNO055 board has an external 32 kHz crystal, which improves its accuracy.
+    pub bno055_external_crystal: bool,
+    // The settings below only apply to the MPU6050. The BNO055 uses fixed ranges in fusion mode.
+    /// Accelerometer full-scale range in g. One of 2, 4, 8 or 16.
+    pub accel_range_g: u8,
+    /// Gyroscope full-scale range in degrees per second. One of 250, 500, 1000 or 2000.
+    pub gyro_range_dps: u16,
+    /// Digital low-pass filter bandwidth in Hz. One of 260, 184, 94, 44, 21, 10 or 5.
+    pub dlpf_bandwidth_hz: u16,
+    /// Sample rate = gyroscope output rate / (1 + sample_rate_divider).
+    pub sample_rate_divider: u8,
+    /// Fraction of the full-scale range above which a reading is flagged as saturated.
+    pub saturation_threshold: f32,
+    /// Optional high-range accelerometer used once the MPU6050 saturates.
+    pub high_g: Option<HighGConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImuSensor {
+    Mpu6050,
+    Bno055,
+}
+
+/// Settings for the data log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Format of the main log, which has every field at full rate.
+    pub format: LogFormat,
+    /// How often the log is flushed and synced to the SD card. It is also synced on every state
+    /// transition.
+    pub sync_interval_ms: u64,
+    /// Directory the logs are written to. Created if it doesn't exist.
+    pub directory: String,
+    /// Longest time we expect to log for, from boot to shutdown (including the recovery beacon).
+    /// Arming is refused if there isn't enough free space for a log this long.
+    pub max_flight_seconds: u64,
+    /// Whether to delete the logs of old pad tests (runs where no launch was detected) on startup.
+    pub prune_pad_tests: bool,
+    /// How many of the most recent pad tests to keep when pruning.
+    pub pad_tests_to_keep: usize,
+    /// Seconds of full-rate data held in memory on the pad, and written to the log at launch.
+    pub pre_launch_buffer_seconds: f32,
+    /// Interval between the records logged on the pad, older than the pre-launch buffer. 0 logs
+    /// every record.
+    pub pad_log_interval_ms: u64,
+    /// Extra logs written alongside the main one, each added with a `[[logging.outputs]]` section.
+    pub outputs: Vec<LogOutputConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Csv,
+            sync_interval_ms: 1000,
+            directory: String::from("logs"),
+            max_flight_seconds: 4000,
+            prune_pad_tests: false,
+            pad_tests_to_keep: 3,
+            pre_launch_buffer_seconds: 10.0,
+            pad_log_interval_ms: 500,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, but slow to write and large.
+    Csv,
+    /// Fixed-size records with a CRC per block, see `binlog`. Convert to CSV with `salt-log`.
+    Binary,
+}
+
+/// An extra log with its own rate and fields, e.g. a decimated csv that's quick to look at.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LogOutputConfig {
+    /// Added to the main log's file name, e.g. `_10hz` for `flight_0007_<date>_10hz.csv`.
+    pub suffix: String,
+    pub format: LogFormat,
+    /// Minimum time between records. 0 logs every record.
+    pub interval_ms: u64,
+    /// Names of the fields to log, as in the main log's header. Empty logs every field.
+    pub fields: Vec<String>,
+}
+
+impl Default for LogOutputConfig {
+    fn default() -> Self {
+        LogOutputConfig {
+            suffix: String::from("_decimated"),
+            format: LogFormat::Csv,
+            interval_ms: 100,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Settings for the diagnostic messages (everything that isn't flight data or an event).
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    /// Messages below this level are dropped.
+    pub level: LogLevel,
+    /// Where messages go. Any combination of stderr, file and syslog (picked up by journald).
+    pub sinks: Vec<DiagnosticsSink>,
+    /// File the `file` sink appends to.
+    pub file_path: String,
+    /// A warning or error from the same place in the code is printed at most once per interval.
+    /// The number of suppressed repeats is added to the next one.
+    pub rate_limit_ms: u64,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            level: LogLevel::Info,
+            sinks: vec![DiagnosticsSink::Stderr],
+            file_path: String::from("logs/diagnostics.log"),
+            rate_limit_ms: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticsSink {
+    Stderr,
+    File,
+    Syslog,
+}
+
+/// Settings for the optional high-g accelerometer, enabled by adding an `[imu.high_g]` section.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HighGConfig {
+    pub sensor: HighGSensor,
+    /// 7-bit I2C address of the sensor.
+    pub address: u8,
+    /// Full-scale range in g. Fixed at 200 for the ADXL375; 100, 200 or 400 for the H3LIS331.
+    pub range_g: u16,
+    /// Fraction of the MPU6050 saturation limit at which we start blending in the high-g
+    /// readings. At the saturation limit only the high-g readings are used.
+    pub blend_start_fraction: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighGSensor {
+    Adxl375,
+    H3lis331,
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        ImuConfig {
+            sensor: ImuSensor::Mpu6050,
+            bno055_address: 0x28,
+            bno055_external_crystal: true,
+            accel_range_g: 16,
+            gyro_range_dps: 2000,
+            dlpf_bandwidth_hz: 44,
+            sample_rate_divider: 0,
+            saturation_threshold: 0.98,
+            high_g: None,
+        }
+    }
+}
+
+impl Default for HighGConfig {
+    fn default() -> Self {
+        HighGConfig {
+            sensor: HighGSensor::Adxl375,
+            address: 0x53,
+            range_g: 200,
+            blend_start_fraction: 0.8,
+        }
+    }
+}
+
+/// Settings for the serial GPS receiver.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GpsConfig {
+    /// Serial port the GPS module is connected to.
+    pub port: String,
+    pub baud_rate: u32,
+    /// How often the position is sent in the telemetry. After landing it is sent every time.
+    pub transmit_interval_ms: u64,
+}
+
+impl Default for GpsConfig {
+    fn default() -> Self {
+        GpsConfig {
+            port: String::from("/dev/ttyUSB0"),
+            baud_rate: 9600,
+            transmit_interval_ms: 1000,
+        }
+    }
+}
+
+/// Settings for reading the battery voltage from sysfs.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BatteryConfig {
+    /// File containing the raw battery voltage reading.
+    pub voltage_path: String,
+    /// Multiplier converting the raw reading to volts.
+    pub scale: f32,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        BatteryConfig {
+            voltage_path: String::from("/sys/class/hwmon/hwmon0/in1_input"),
+            // hwmon reports millivolts:
+            scale: 0.001,
+        }
+    }
+}
+
+/// Settings for ignoring the barometer near Mach 1, where the pressure readings are garbage. While
+/// locked out, altitude and velocity come from integrating the accelerometer and apogee can't be
+/// detected.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MachLockoutConfig {
+    pub enabled: bool,
+    /// Estimated velocity in m/s above which the barometer is ignored.
+    pub velocity_threshold: f32,
+    /// Estimated velocity in m/s below which the barometer is used again. Lower than the
+    /// threshold, so the lockout doesn't flicker on and off.
+    pub release_velocity: f32,
+}
+
+impl Default for MachLockoutConfig {
+    fn default() -> Self {
+        MachLockoutConfig {
+            enabled: true,
+            velocity_threshold: 250.0,
+            release_velocity: 220.0,
+        }
+    }
+}
+
+/// Settings for the pyro channels that deploy the parachutes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PyroConfig {
+    pub backend: GpioBackend,
+    /// Input that reads high while the hardware arming switch is on. Nothing fires without it.
+    pub arming_pin: u32,
+    /// How long a channel's output stays on when it fires.
+    pub fire_duration_ms: u64,
+    pub channels: Vec<PyroChannelConfig>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpioBackend {
+    /// Real pins through `/sys/class/gpio`.
+    Sysfs,
+    /// Pins in memory, for testing without any hardware.
+    Simulated,
+}
+
+/// One pyro channel, added with a `[[pyro.channels]]` section. Everything but the continuity pin
+/// is required, and unknown fields are rejected, so a typo can't turn into a charge on the wrong
+/// pin or at the wrong time.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PyroChannelConfig {
+    pub name: String,
+    /// Output that fires the charge while high.
+    pub fire_pin: u32,
+    /// Input that reads high while there's continuity through the igniter.
+    pub continuity_pin: Option<u32>,
+    pub trigger: PyroTrigger,
+}
+
+/// The state transition a channel fires on.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PyroTrigger {
+    /// Entering the drogue descent.
+    Apogee,
+    /// Entering the main descent.
+    Main,
+}
+
+impl Default for PyroConfig {
+    fn default() -> Self {
+        PyroConfig {
+            backend: GpioBackend::Sysfs,
+            arming_pin: 17,
+            fire_duration_ms: 1000,
+            channels: Vec::new(),
+        }
+    }
+}
+
+/// Settings for the descent under drogue and main parachutes. Altitudes are above the pad.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DeploymentConfig {
+    /// Altitude at which the main parachute comes out, ending the drogue descent. For a single
+    /// deploy rocket, set this above the expected apogee.
+    pub main_deploy_altitude: f32,
+    /// Descent rate in m/s under the drogue above which it's considered to have failed.
+    pub max_drogue_descent_rate: f32,
+    /// Descent rate in m/s under the main above which it's considered to have failed.
+    pub max_main_descent_rate: f32,
+}
+
+impl Default for DeploymentConfig {
+    fn default() -> Self {
+        DeploymentConfig {
+            main_deploy_altitude: 150.0,
+            max_drogue_descent_rate: 40.0,
+            max_main_descent_rate: 10.0,
+        }
+    }
+}
+
+/// Limits that abort the countdown or fault the flight computer, see `safety`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SafetyConfig {
+    /// Tilt from vertical on the pad, in degrees, above which the countdown is aborted. Measured
+    /// from the IMU's z axis, which should point along the rocket.
+    pub max_pad_tilt_degrees: f32,
+    /// Battery voltage below which the countdown is aborted. Needs a `[battery]` section.
+    pub min_battery_voltage: Option<f32>,
+    /// How long a limit has to be exceeded, or the barometer or accelerometer has to fail to read,
+    /// before we act on it.
+    pub trip_delay_ms: u64,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        SafetyConfig {
+            max_pad_tilt_degrees: 20.0,
+            min_battery_voltage: None,
+            trip_delay_ms: 1000,
+        }
+    }
+}
+
+/// How long a transition's guard has to hold before the transition fires. Both have to be met.
+/// Unknown fields are rejected, so a typo doesn't silently leave the guard undebounced.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebounceConfig {
+    /// Consecutive cycles the guard has to hold for:
+    pub samples: u32,
+    /// Time since the guard started holding:
+    pub duration_ms: u64,
+}
+
+impl DebounceConfig {
+    /// Fires on the first cycle the guard holds.
+    pub const NONE: DebounceConfig = DebounceConfig {
+        samples: 1,
+        duration_ms: 0,
+    };
+
+    pub const fn samples(samples: u32) -> Self {
+        DebounceConfig {
+            samples,
+            duration_ms: 0,
+        }
+    }
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        DebounceConfig::NONE
+    }
+}
+
+/// Settings for what happens after landing.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RecoveryConfig {
+    /// Whether to transmit a recovery beacon after landing. If false, we shut down right away.
+    pub beacon_enabled: bool,
+    /// Time between beacon transmissions.
+    pub beacon_interval_ms: u64,
+    /// How long to keep transmitting the beacon before shutting down.
+    pub beacon_duration_seconds: u64,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            beacon_enabled: true,
+            beacon_interval_ms: 5000,
+            beacon_duration_seconds: 3600,
+        }
+    }
+}
+
+/// Settings for the camera recording, started with the boot command.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    pub width: u32,
+    pub height: u32,
+    pub framerate: u32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            width: 1920,
+            height: 1080,
+            framerate: 30,
+        }
+    }
+}
+
+/// Settings for what happens once we reach the Shutdown state.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Shell command run after everything is saved, e.g. "sudo poweroff".
+    pub poweroff_command: Option<String>,
+}
+
+impl FlightConfig {
+    /// The configuration as TOML, as written at the start of every log.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_else(|e| format!("# failed to serialize config: {}", e))
+    }
+
+    /// Loads the configuration from `path`, falling back to the defaults if there is no such file.
+    /// A file that exists but can't be read or parsed is an error rather than the defaults: a typo
+    /// in the `[pyro]` section would otherwise fly with the pyros silently disabled.
+    pub fn load(path: &str) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warn!("No flight config at {}. Using defaults.", path);
+                Ok(FlightConfig::default())
+            }
+            Err(e) => Err(io::Error::new(e.kind(), format!("{}: {}", path, e))),
+        }
+    }
+
+    /// Parses a configuration from the contents of a flight config file.
+    pub fn from_toml(contents: &str) -> io::Result<Self> {
+        toml::from_str(contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bad_pyro_section_is_an_error() {
+        let contents = r#"
+            [pyro]
+            arming_pin = 5
+
+            [[pyro.channels]]
+            name = "drogue"
+            fire_pin = 6
+            trigger = "apogee"
+            continuty_pin = 13
+        "#;
+        let error = FlightConfig::from_toml(contents).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("continuty_pin"), "{}", error);
+    }
+
+    #[test]
+    fn a_valid_pyro_section_is_loaded() {
+        let contents = r#"
+            [pyro]
+            arming_pin = 5
+
+            [[pyro.channels]]
+            name = "drogue"
+            fire_pin = 6
+            trigger = "apogee"
+            continuity_pin = 13
+        "#;
+        let config = FlightConfig::from_toml(contents).unwrap();
+        let pyro = config.pyro.unwrap();
+        assert_eq!(pyro.channels.len(), 1);
+        assert_eq!(pyro.channels[0].continuity_pin, Some(13));
+    }
+
+    #[test]
+    fn the_example_config_parses() {
+        FlightConfig::from_toml(include_str!("../flight_config.toml")).unwrap();
+    }
+
+    #[test]
+    fn a_missing_file_uses_the_defaults() {
+        let config = FlightConfig::load("/nonexistent/flight_config.toml").unwrap();
+        assert!(config.pyro.is_none());
+    }
+}
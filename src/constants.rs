@@ -2,20 +2,71 @@
 
 /// Velocity above which the rocket is considered to be in powered flight:
 pub const TAKEOFF_VELOCITY_METERS_PER_SECOND: f32 = 10.0;
+/// Acceleration magnitude (including gravity) above which the motor may have ignited:
+pub const LAUNCH_ACCELERATION_METERS_PER_SECOND_SQUARED: f32 =
+    2.5 * GRAVITY_METERS_PER_SECOND_SQUARED;
+/// Consecutive samples the acceleration has to stay above the threshold for:
+pub const LAUNCH_ACCELERATION_SAMPLES: u32 = 4;
+/// Altitude gain over the pad that confirms a launch, if the velocity doesn't yet:
+pub const LAUNCH_ALTITUDE_GAIN_METERS: f32 = 3.0;
+/// Seconds after the acceleration trigger within which the barometer has to confirm the launch:
+pub const LAUNCH_CONFIRMATION_SECONDS: f32 = 3.0;
 /// Velocity % below which the rocket is considered to be in coast:
 pub const MAX_VELOCITY_THRESHOLD: f32 = 0.96;
+/// Acceleration magnitude below which the motor has burnt out. Without thrust the accelerometer
+/// only measures drag, so it reads less than gravity:
+pub const BURNOUT_ACCELERATION_METERS_PER_SECOND_SQUARED: f32 = GRAVITY_METERS_PER_SECOND_SQUARED;
+/// Consecutive samples the acceleration has to stay below the burnout threshold for:
+pub const BURNOUT_SAMPLES: u32 = 3;
 
-/// Altitude % below which the rocket is considered to be in free fall:
-pub const MAX_ALTITUDE_THRESHOLD: f32 = 0.94;
+/// Apogee is declared once at least this many of the criteria below agree:
+pub const APOGEE_VOTES_REQUIRED: usize = 2;
+/// Consecutive samples the velocity has to be at or below zero for:
+pub const APOGEE_VELOCITY_SAMPLES: u32 = 3;
+/// Descent below the maximum altitude that counts as barometric descent:
+pub const APOGEE_ALTITUDE_DROP_METERS: f32 = 3.0;
+/// Added to the coast time without drag (burnout velocity / g) for the backup timer:
+pub const APOGEE_TIMER_MARGIN_SECONDS: f32 = 1.0;
+/// Maximum time we can be in coast, in case none of the criteria agree:
+pub const MAX_COAST_SECONDS: f32 = 60.0;
 
-/// Altitude below which the rocket is considered to have landed from free fall:
+/// Samples the rocket has to be stationary for to be considered landed (5 s at the loop rate):
+pub const LANDED_WINDOW_SAMPLES: usize = 100;
+/// Speed below which the rocket may be stationary:
+pub const LANDED_VELOCITY_METERS_PER_SECOND: f32 = 1.0;
+/// Standard deviation of the acceleration magnitude below which the rocket may be stationary:
+pub const LANDED_ACCELERATION_STDDEV_METERS_PER_SECOND_SQUARED: f32 = 0.5;
+/// Spread of the altitude over the window below which the rocket may be stationary:
+pub const LANDED_ALTITUDE_RANGE_METERS: f32 = 2.0;
+
+/// Altitude below which the rocket may have landed, if it's slow but too noisy to ever look
+/// stationary:
 pub const GROUND_ALTITUDE_METERS: f32 = 15.0;
 
-/// Seconds after which the rocket is considered to have landed:
+/// Seconds the rocket has to stay slow below the ground altitude to be considered landed that way:
 pub const SECONDS_TO_CONSIDERED_LANDED: u64 = 10;
 
-/// Maximum time we can be in free fall:
-pub const MAX_FREE_FALL_SECONDS: u64 = 300;
+/// Maximum time from apogee to landing:
+pub const MAX_DESCENT_SECONDS: u64 = 300;
+
+/// Samples the descent rate is averaged over (1 s at the loop rate):
+pub const DESCENT_RATE_SAMPLES: usize = 20;
+/// Time a parachute gets to slow us down after the descent phase starts, before checking for a
+/// failure:
+pub const CHUTE_DEPLOY_GRACE_SECONDS: f32 = 3.0;
 
 pub const VELOCITY_FROM_ALTITUDE_WINDOW_SIZE: usize = 15;
+/// Fraction of the difference to the barometric velocity that the accelerometer-integrated
+/// velocity is corrected by each sample, while the barometer is trusted:
+pub const INERTIAL_VELOCITY_CORRECTION: f32 = 0.05;
 pub const ALTITUDE_DEADBAND_METERS: f32 = 0.05;
+
+/// Consecutive good reads a failing sensor needs before it's recorded as recovered (1 s at the
+/// loop rate), so a flapping sensor doesn't fill the event log:
+pub const SENSOR_RECOVERY_SAMPLES: u32 = 20;
+
+/// Time the main loop sleeps between updates, to avoid flooding the I2C bus:
+pub const MAIN_LOOP_INTERVAL_MS: u64 = 50;
+
+/// Standard gravity, used to convert accelerometer readings from g:
+pub const GRAVITY_METERS_PER_SECOND_SQUARED: f32 = 9.80665;
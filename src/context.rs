@@ -1,85 +1,434 @@
 use crate::{
+    battery::Battery,
+    camera::Camera,
+    config::{FlightConfig, PyroTrigger},
     data_processor::{DataProcessor, ProcessorDataPacket},
+    error,
+    events::{EventKind, EventLog, Severity},
+    gps::{Gps, GpsDataPacket},
     imu::{IMU, IMUDataPacket},
+    info,
     logger::Logger,
-    state::{CountdownState, RocketState, StandbyState},
-    transmitter::{Transmitter, TransmitterDataPacket},
+    metadata::flight_metadata,
+    pyro::Pyro,
+    safety::{SafetyMonitor, SafetyTrip},
+    state::{RocketState, StandbyState, state_letter},
+    summary::FlightSummary,
+    trace,
+    transitions::{Debouncer, GuardInputs, Transition, TransitionAction},
+    transmitter::{BeaconDataPacket, Transmitter, TransmitterDataPacket},
+    warn,
 };
+use std::process::Command;
 use std::time::{Duration, Instant};
 
 
 pub struct Context {
+    pub config: FlightConfig,
     pub state: RocketState,
     pub data_processor: DataProcessor,
     pub imu: IMU,
+    pub gps: Option<Gps>,
+    pub battery: Option<Battery>,
+    pub camera: Option<Camera>,
+    pub pyro: Option<Pyro>,
+    pub safety: SafetyMonitor,
+    pub debouncer: Debouncer,
     pub transmitter: Option<Transmitter>,
     pub logger: Logger,
-    last_transmit: Option<Instant>
+    pub events: EventLog,
+    pub flight_summary: FlightSummary,
+    last_transmit: Option<Instant>,
+    last_gps_transmit: Option<Instant>,
 }
 
 impl Context {
-    pub fn new() -> Self {
+    pub fn new(config: FlightConfig) -> Self {
+        let imu = IMU::new(&config.imu);
+        let metadata = flight_metadata(&config, &imu.sensor_report);
+        let logger = Logger::new(&config.logging, &metadata);
+        let events = EventLog::new(&logger.path_with_suffix("_events.csv"));
         Context {
             state: RocketState::Standby(StandbyState {}),
-            data_processor: DataProcessor::new(),
-            imu: IMU::new(),
+            data_processor: DataProcessor::new(&config.imu, &config.mach_lockout),
+            imu,
+            gps: config
+                .gps
+                .as_ref()
+                .and_then(|gps_config| match Gps::new(gps_config) {
+                    Ok(gps) => {
+                        info!("GPS opened on {}.", gps_config.port);
+                        Some(gps)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to open GPS on {}: {}. Continuing without it.",
+                            gps_config.port, e
+                        );
+                        None
+                    }
+                }),
+            battery: config.battery.as_ref().map(Battery::new),
+            camera: config.camera.as_ref().map(Camera::new),
+            pyro: config
+                .pyro
+                .as_ref()
+                .and_then(|pyro_config| match Pyro::new(pyro_config) {
+                    Ok(pyro) => Some(pyro),
+                    Err(e) => {
+                        error!(
+                            "Failed to set up the pyro channels: {}. Nothing will fire!",
+                            e
+                        );
+                        None
+                    }
+                }),
             // transmitter: None,
             transmitter: Some(Transmitter::new("/dev/ttyS0")),
-            logger: Logger::new(),
+            safety: SafetyMonitor::new(&config.safety),
+            debouncer: Debouncer::new(&config.debounce),
+            logger,
+            events,
+            flight_summary: FlightSummary::default(),
             last_transmit: None,
+            last_gps_transmit: None,
+            config,
         }
     }
 
     pub fn update(&mut self) {
         let imu_data_packet = self.imu.get_data_packet();
+        let gps_data_packet = self.gps.as_mut().map(|gps| {
+            gps.poll();
+            gps.get_data_packet()
+        });
 
+        let mut sensor_errors = self.imu.read_errors().to_vec();
+        if let Some(error) = self.gps.as_ref().and_then(|gps| gps.read_error()) {
+            sensor_errors.push(("GPS", error.to_string()));
+        }
+        self.events.update_sensor_errors(&sensor_errors);
+
+        let was_mach_locked_out = self.data_processor.mach_lockout;
         self.data_processor.update(&imu_data_packet);
+        if self.data_processor.mach_lockout != was_mach_locked_out {
+            let payload = if self.data_processor.mach_lockout {
+                format!(
+                    "engaged at {:.1} m/s",
+                    self.data_processor.vertical_velocity
+                )
+            } else {
+                format!(
+                    "released at {:.1} m/s",
+                    self.data_processor.vertical_velocity
+                )
+            };
+            self.events
+                .record(Severity::Info, EventKind::MachLockout, payload);
+        }
         // Get the processed data packets:
         let processor_data_packet = self.data_processor.get_processor_data_packet();
 
         // Update the state with the new data
         self.state.update_internal(&processor_data_packet);
+        if let Some(failure) = self.state.check_chute_failure(&self.config.deployment) {
+            self.events
+                .record(Severity::Error, EventKind::ChuteFailure, failure);
+        }
 
-        if let Some(new_state) = self.state.should_transition(self) {
-            self.state = new_state;
+        let command = self.poll_command();
+        if command.as_deref() == Some("SALT ABORT") && !self.state.is_before_launch() {
+            self.events.record(
+                Severity::Warning,
+                EventKind::Command,
+                "ignoring SALT ABORT after launch, the recovery sequence keeps running",
+            );
+        }
+        let safety_trip = self.check_safety(&processor_data_packet);
+        let inputs = GuardInputs {
+            now: Instant::now(),
+            processor: &processor_data_packet,
+            config: &self.config,
+            command: command.as_deref(),
+            safety_trip: safety_trip.as_ref(),
+        };
+        if let Some(transition) = self.debouncer.evaluate(&self.state, &inputs) {
+            self.apply_transition(transition);
         }
+        self.flight_summary.update(&processor_data_packet);
+        if let Some(pyro) = &mut self.pyro {
+            pyro.update();
+        }
+        self.retry_pyros();
+
         // Transmit every 0.5 seconds
         let now = Instant::now();
+        let transmit_interval = if self.state.is_beacon_mode() {
+            Duration::from_millis(self.config.recovery.beacon_interval_ms)
+        } else {
+            Duration::from_millis(200)
+        };
         let should_transmit = match self.last_transmit {
-            Some(last) => now.duration_since(last) >= Duration::from_millis(200),
+            Some(last) => now.duration_since(last) >= transmit_interval,
             None => true,
         };
 
-        if should_transmit {
+        if should_transmit && self.state.is_beacon_mode() {
+            // Only the low-rate beacon is sent after landing, an abort or a fault:
+            let beacon_data_packet = self.prepare_beacon_data_packet(gps_data_packet.as_ref());
+            if let Some(transmitter) = &mut self.transmitter {
+                transmitter.transmit_beacon(&beacon_data_packet);
+            }
+            self.last_transmit = Some(now);
+        } else if should_transmit {
             let transmitter_data_packet =
                 self.prepare_transmitter_data_packet(&imu_data_packet, &processor_data_packet);
+            let pending = self.debouncer.pending(now);
             if let Some(transmitter) = &mut self.transmitter {
                 transmitter.transmit(&transmitter_data_packet);
+                if !pending.is_empty() {
+                    transmitter.transmit_debounce(&pending);
+                }
             }
             self.last_transmit = Some(now);
+
+            // Send the position at a lower rate, except after landing when it's all we care about:
+            if let (Some(gps_data_packet), Some(gps_config)) = (&gps_data_packet, &self.config.gps)
+            {
+                let landed = matches!(self.state, RocketState::Landed(_));
+                let should_transmit_gps = match self.last_gps_transmit {
+                    Some(last) => {
+                        landed
+                            || now.duration_since(last)
+                                >= Duration::from_millis(gps_config.transmit_interval_ms)
+                    }
+                    None => true,
+                };
+                if should_transmit_gps {
+                    if let Some(transmitter) = &mut self.transmitter {
+                        transmitter.transmit_gps(gps_data_packet);
+                    }
+                    self.last_gps_transmit = Some(now);
+                }
+            }
         }
 
+        // Events go out as soon as they happen, whatever the telemetry rate:
+        self.transmit_events();
+
         // Match state name to a single character for logging:
-        let state_char = match self.state.name() {
-            "Standby" => 'S',
-            "Countdown" => 'C',
-            "MotorBurn" => 'M',
-            "Coast" => 'O',
-            "FreeFall" => 'F',
-            "Landed" => 'L',
-            "Shutdown" => 'X',
-            _ => 'U', // Unknown
-        };
+        let state_char = state_letter(self.state.name());
 
         // Log data
-        self.logger
-            .log_packets(&imu_data_packet, &processor_data_packet, &state_char);
-
-        println!("Pressure alt: {} m", imu_data_packet.pressure_alt);
-        println!("Current Velocity: {} m/s", processor_data_packet.vertical_velocity);
-        // println!("Max Velocity: {} m/s", processor_data_packet.maximum_velocity);
-        println!("Accel: {} m/s^2", imu_data_packet.acceleration[2]);
-        println!("");
+        self.logger.log_packets(
+            &imu_data_packet,
+            &processor_data_packet,
+            gps_data_packet.as_ref(),
+            &state_char,
+        );
+
+        trace!(
+            "Pressure alt: {} m, velocity: {} m/s, accel: {} m/s^2",
+            imu_data_packet.pressure_alt,
+            processor_data_packet.vertical_velocity,
+            imu_data_packet.acceleration[2]
+        );
+    }
+
+    /// Whether we've reached the Shutdown state, and the main loop should stop.
+    pub fn is_shutdown(&self) -> bool {
+        matches!(self.state, RocketState::Shutdown)
+    }
+
+    /// Finalises everything once the main loop has stopped: stops the camera, closes the
+    /// transmitter, saves the log and the flight summary, then runs the poweroff command if
+    /// there is one.
+    pub fn shutdown(&mut self) {
+        if let Some(camera) = &mut self.camera {
+            camera.stop();
+        }
+
+        if let Some(transmitter) = self.transmitter.take() {
+            self.flight_summary.link_stats = Some(transmitter.stats());
+            transmitter.close();
+        }
+
+        self.logger.end_pad_logging();
+        if let Err(e) = self.logger.sync() {
+            error!("Failed to sync log file: {}", e);
+        }
+        if let Err(e) = self.events.sync() {
+            error!("Failed to sync event log: {}", e);
+        }
+
+        let summary_path = self.logger.path_with_suffix("_summary.txt");
+        match self.flight_summary.write_to_file(&summary_path) {
+            Ok(()) => info!("Flight summary written to {}.", summary_path),
+            Err(e) => error!("Failed to write flight summary to {}: {}", summary_path, e),
+        }
+
+        if let Some(command) = &self.config.shutdown.poweroff_command {
+            info!("Running poweroff command: {}", command);
+            match Command::new("sh").arg("-c").arg(command).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("Poweroff command exited with {}", status),
+                Err(e) => warn!("Failed to run poweroff command: {}", e),
+            }
+        }
+    }
+
+    /// Switches to the new state, recording the transition and doing whatever entering that state
+    /// involves.
+    fn apply_transition(&mut self, transition: Transition) {
+        let new_state = transition.state;
+        self.events.record(
+            Severity::Info,
+            EventKind::StateTransition,
+            format!(
+                "{} -> {}: {}",
+                self.state.name(),
+                new_state.name(),
+                transition.reason
+            ),
+        );
+        self.flight_summary
+            .record_transition(&self.state, &new_state);
+        match transition.action {
+            TransitionAction::None => {}
+            TransitionAction::ReportPyroStatus => self.record_pyro_status(),
+            TransitionAction::FirePyros(trigger) => self.fire_pyros(trigger),
+            TransitionAction::DisarmPyros => self.disarm_pyros(),
+        }
+        // Once we're off the pad, keep the data from just before liftoff and log at full rate:
+        if !new_state.is_before_launch() {
+            self.logger.end_pad_logging();
+        }
+        // State changes are the moments we care most about, so get them on disk right away:
+        if let Err(e) = self.logger.sync() {
+            error!("Failed to sync log file: {}", e);
+        }
+        self.state = new_state;
+    }
+
+    /// Returns a command from the uplink if one arrived, recording it.
+    fn poll_command(&mut self) -> Option<String> {
+        let command = self.transmitter.as_mut()?.poll_command()?;
+        self.events.record(
+            Severity::Info,
+            EventKind::Command,
+            format!("received {}", command),
+        );
+        Some(command)
+    }
+
+    /// Runs the safety checks, until launch.
+    fn check_safety(&mut self, processor_data_packet: &ProcessorDataPacket) -> Option<SafetyTrip> {
+        if !self.state.is_before_launch() {
+            return None;
+        }
+        let on_pad = matches!(self.state, RocketState::Countdown(_));
+        // Only read the battery when it's checked, it's a file read:
+        let battery_voltage = if on_pad {
+            self.battery
+                .as_ref()
+                .and_then(|battery| battery.read_voltage())
+        } else {
+            None
+        };
+        self.safety.check(
+            on_pad,
+            self.imu.read_errors(),
+            processor_data_packet.acceleration,
+            battery_voltage,
+        )
+    }
+
+    /// Turns off and disarms every pyro channel.
+    fn disarm_pyros(&mut self) {
+        if let Some(pyro) = &mut self.pyro {
+            pyro.disarm();
+            self.events
+                .record(Severity::Warning, EventKind::PyroStatus, "pyros disarmed");
+        }
+    }
+
+    /// Fires the pyro channels for `trigger`, recording each attempt.
+    fn fire_pyros(&mut self, trigger: PyroTrigger) {
+        let Some(pyro) = &mut self.pyro else { return };
+        for attempt in pyro.fire(trigger) {
+            match attempt.result {
+                Ok(()) => self.events.record(
+                    Severity::Info,
+                    EventKind::PyroFire,
+                    format!("{} fired", attempt.channel),
+                ),
+                Err(e) => self.events.record(
+                    Severity::Error,
+                    EventKind::PyroFire,
+                    format!("{} did not fire: {}", attempt.channel, e),
+                ),
+            }
+        }
+    }
+
+    /// Fires the channels that couldn't fire when their trigger was reached, while still under
+    /// the parachutes. After landing a late charge would only be a danger to the recovery crew.
+    fn retry_pyros(&mut self) {
+        if !matches!(
+            self.state,
+            RocketState::DrogueDescent(_) | RocketState::MainDescent(_)
+        ) {
+            return;
+        }
+        let Some(pyro) = &mut self.pyro else { return };
+        for attempt in pyro.retry() {
+            self.events.record(
+                Severity::Info,
+                EventKind::PyroFire,
+                format!("{} fired on retry", attempt.channel),
+            );
+        }
+    }
+
+    /// Records the state of the arming switch and igniter continuity, so we know before liftoff
+    /// whether the charges can fire.
+    fn record_pyro_status(&mut self) {
+        let Some(pyro) = &mut self.pyro else { return };
+        let armed = pyro.is_armed();
+        let continuity = pyro.continuity();
+        let all_closed = continuity.iter().all(|(_, closed)| *closed);
+        let channels: Vec<String> = continuity
+            .iter()
+            .map(|(name, closed)| {
+                format!("{} {}", name, if *closed { "continuity" } else { "open" })
+            })
+            .collect();
+        let severity = if armed && all_closed {
+            Severity::Info
+        } else {
+            Severity::Warning
+        };
+        self.events.record(
+            severity,
+            EventKind::PyroStatus,
+            format!(
+                "arming switch {}; {}",
+                if armed { "on" } else { "off" },
+                if channels.is_empty() {
+                    String::from("no continuity sensing")
+                } else {
+                    channels.join(", ")
+                }
+            ),
+        );
+    }
+
+    fn transmit_events(&mut self) {
+        for event in self.events.take_pending_telemetry() {
+            if let Some(transmitter) = &mut self.transmitter {
+                transmitter.transmit_event(&event);
+            }
+        }
     }
 
     fn prepare_transmitter_data_packet(
@@ -97,44 +446,108 @@ impl Context {
         }
     }
 
+    fn prepare_beacon_data_packet(
+        &self,
+        gps_data_packet: Option<&GpsDataPacket>,
+    ) -> BeaconDataPacket {
+        BeaconDataPacket {
+            gps: gps_data_packet.cloned(),
+            battery_voltage: self
+                .battery
+                .as_ref()
+                .and_then(|battery| battery.read_voltage()),
+            max_alt: self.flight_summary.max_altitude,
+            max_vel: self.flight_summary.max_velocity,
+            flight_time: self
+                .flight_summary
+                .flight_time()
+                .map_or(0.0, |flight_time| flight_time.as_secs_f32()),
+        }
+    }
+
     // / Waits for the "SALT BOOT" command from the transmitter, so we can start the hot loop.
     // / The main loop will call this function in a loop until it returns true.
     pub fn wait_for_boot_command(&mut self) -> bool {
-        let command = if let Some(transmitter) = &mut self.transmitter {
-            transmitter.read()
-        } else {
-            eprintln!("No transmitter available, starting countdown anyway.");
-            self.state = RocketState::Countdown(CountdownState {});
-            return true;
-        };
+        // e.g. why the last attempt to arm was refused:
+        self.transmit_events();
 
-        match command {
-            Ok(data) if data == "SALT BOOT" => {
-                println!("Received SALT BOOT command, transitioning to Countdown state.");
-                self.state = RocketState::Countdown(CountdownState {});
-                true
+        // Without an uplink nobody can send the boot command, so carry on as if they had:
+        let read = self
+            .transmitter
+            .as_mut()
+            .map(|transmitter| transmitter.read());
+        let (command, received) = match read {
+            Some(Ok(data)) if data == "wait" => {
+                trace!("waiting for boot command...");
+                return false;
             }
-            Ok(data) if data == "wait" => {
-                println!("waiting for boot command...");
-                false
+            Some(Ok(data)) => (data, true),
+            Some(Err(e)) => {
+                self.events.record(
+                    Severity::Warning,
+                    EventKind::Command,
+                    format!(
+                        "failed to read from transmitter: {}, starting countdown anyway",
+                        e
+                    ),
+                );
+                (String::from("SALT BOOT"), false)
             }
-
-            Ok(data) => {
-                println!(
-                    "Unknown command received: {}. Staying in Standby state.",
-                    data
+            None => {
+                self.events.record(
+                    Severity::Warning,
+                    EventKind::Command,
+                    "no transmitter available, starting countdown anyway",
                 );
-                false
+                (String::from("SALT BOOT"), false)
             }
+        };
 
-            Err(e) => {
-                eprintln!(
-                    "Failed to read from transmitter: {}, starting countdown anyway.",
-                    e
-                );
-                self.state = RocketState::Countdown(CountdownState {});
+        // Refuse to arm if there isn't enough space left for the log:
+        if command == "SALT BOOT"
+            && let Err(e) = self.logger.check_free_space()
+        {
+            self.events.record(
+                Severity::Error,
+                EventKind::Command,
+                format!("refusing to arm: {}", e),
+            );
+            if self.transmitter.is_none() {
+                // Nothing can change without a command, so don't spin:
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            return false;
+        }
+
+        let processor_data_packet = self.data_processor.get_processor_data_packet();
+        let inputs = GuardInputs {
+            now: Instant::now(),
+            processor: &processor_data_packet,
+            config: &self.config,
+            command: Some(&command),
+            safety_trip: None,
+        };
+        match self.debouncer.evaluate(&self.state, &inputs) {
+            // After an abort the main loop still runs, for the beacon and a clean shutdown:
+            Some(transition) => {
+                if received {
+                    self.events.record(
+                        Severity::Info,
+                        EventKind::Command,
+                        format!("received {}", command),
+                    );
+                }
+                self.apply_transition(transition);
                 true
             }
+            None => {
+                self.events.record(
+                    Severity::Warning,
+                    EventKind::Command,
+                    format!("unknown command received: {}, staying in Standby", command),
+                );
+                false
+            }
         }
     }
 }
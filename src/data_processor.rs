@@ -1,18 +1,36 @@
 //! Data processing logic for the rocket.
 
-use crate::constants::{ALTITUDE_DEADBAND_METERS, VELOCITY_FROM_ALTITUDE_WINDOW_SIZE};
+use crate::config::{ImuConfig, MachLockoutConfig};
+use crate::constants::{
+    ALTITUDE_DEADBAND_METERS, GRAVITY_METERS_PER_SECOND_SQUARED, INERTIAL_VELOCITY_CORRECTION,
+    VELOCITY_FROM_ALTITUDE_WINDOW_SIZE,
+};
 use fixed_deque::Deque;
 
-use crate::imu::IMUDataPacket;
+use crate::imu::{IMUDataPacket, accel_saturation_limit};
 
 pub struct DataProcessor {
     pub max_altitude: f32,
     pub max_velocity: f32,
     pub vertical_velocity: f32,
     pub current_altitude: f32,
+    /// Acceleration in m/s^2, blended from the MPU6050 and the high-g accelerometer:
+    pub acceleration: [f32; 3],
+    /// How much of `acceleration` came from the high-g accelerometer, from 0 to 1:
+    pub high_g_weight: f32,
+    /// Whether the barometer is being ignored because we're close to Mach 1:
+    pub mach_lockout: bool,
+    mach_lockout_config: MachLockoutConfig,
+    /// Vertical velocity from integrating the accelerometer, kept close to the barometric one
+    /// while the barometer is trusted:
+    inertial_velocity: f32,
+    /// Unit vector pointing up in the sensor frame, from the acceleration on the pad:
+    up: [f32; 3],
     last_data_packet: Option<IMUDataPacket>,
     velocity_rolling_average: Deque<f32>,
     last_velocity_calculation_packet: Option<IMUDataPacket>,
+    accel_saturation_limit: f32,
+    high_g_blend_start: f32,
 }
 
 pub struct ProcessorDataPacket {
@@ -20,12 +38,28 @@ pub struct ProcessorDataPacket {
     pub vertical_velocity: f32,
     pub maximum_altitude: f32,
     pub maximum_velocity: f32,
+    pub acceleration: [f32; 3],
+    pub high_g_weight: f32,
+    pub mach_lockout: bool,
 }
 
 impl DataProcessor {
-    pub fn new() -> Self {
+    pub fn new(config: &ImuConfig, mach_lockout_config: &MachLockoutConfig) -> Self {
+        let accel_saturation_limit = accel_saturation_limit(config);
+        let blend_start_fraction = config
+            .high_g
+            .as_ref()
+            .map_or(1.0, |high_g| high_g.blend_start_fraction);
         DataProcessor {
             current_altitude: 0.0,
+            acceleration: [0.0, 0.0, 0.0],
+            high_g_weight: 0.0,
+            mach_lockout: false,
+            mach_lockout_config: mach_lockout_config.clone(),
+            inertial_velocity: 0.0,
+            up: [0.0, 0.0, 1.0],
+            accel_saturation_limit,
+            high_g_blend_start: accel_saturation_limit * blend_start_fraction,
             velocity_rolling_average: Deque::new(VELOCITY_FROM_ALTITUDE_WINDOW_SIZE),
             max_altitude: 0.0,
             max_velocity: 0.0,
@@ -40,30 +74,114 @@ impl DataProcessor {
         self.current_altitude = data_packet.pressure_alt;
         self.max_altitude = data_packet.pressure_alt;
         self.max_velocity = 0.0;
+        (self.acceleration, self.high_g_weight) = self.blend_acceleration(data_packet);
+        // At rest the accelerometer measures gravity pointing up:
+        let magnitude = self.acceleration.iter().map(|a| a * a).sum::<f32>().sqrt();
+        if magnitude > 0.5 * GRAVITY_METERS_PER_SECOND_SQUARED {
+            self.up = self.acceleration.map(|a| a / magnitude);
+        }
+        self.inertial_velocity = 0.0;
+        self.mach_lockout = false;
         self.last_data_packet = Some(data_packet.clone());
         self.last_velocity_calculation_packet = Some(data_packet.clone());
         self.velocity_rolling_average.clear();
     }
 
     pub fn update(&mut self, data_packet: &IMUDataPacket) {
-        self.current_altitude = data_packet.pressure_alt;
-        self.max_altitude = self.max_altitude.max(data_packet.pressure_alt);
+        (self.acceleration, self.high_g_weight) = self.blend_acceleration(data_packet);
 
-        self.vertical_velocity = self.calculate_velocity_from_altitude(data_packet);
+        let dt = self.last_data_packet.as_ref().map_or(0.0, |last| {
+            data_packet.timestamp.saturating_sub(last.timestamp) as f32 / 1e9
+        });
+        self.inertial_velocity += self.vertical_acceleration() * dt;
+        self.update_mach_lockout(data_packet);
+
+        if self.mach_lockout {
+            // Dead reckoning until the barometer can be trusted again:
+            self.vertical_velocity = self.inertial_velocity;
+            self.current_altitude += self.vertical_velocity * dt;
+        } else {
+            self.current_altitude = data_packet.pressure_alt;
+            self.vertical_velocity = self.calculate_velocity_from_altitude(data_packet);
+            self.inertial_velocity +=
+                (self.vertical_velocity - self.inertial_velocity) * INERTIAL_VELOCITY_CORRECTION;
+        }
+        // The dead-reckoned altitude drifts, so only the barometer gets to raise the maximum:
+        if !self.mach_lockout {
+            self.max_altitude = self.max_altitude.max(self.current_altitude);
+        }
         self.max_velocity = self.max_velocity.max(self.vertical_velocity);
 
         self.last_data_packet = Some(data_packet.clone());
     }
 
+    /// Acceleration along the up direction from the pad, with gravity removed, in m/s^2.
+    fn vertical_acceleration(&self) -> f32 {
+        let along_up: f32 = (0..3)
+            .map(|axis| self.acceleration[axis] * self.up[axis])
+            .sum();
+        along_up - GRAVITY_METERS_PER_SECOND_SQUARED
+    }
+
+    /// Engages the lockout once the estimated velocity passes the threshold, and releases it once
+    /// it drops below the release velocity.
+    fn update_mach_lockout(&mut self, data_packet: &IMUDataPacket) {
+        if !self.mach_lockout_config.enabled {
+            return;
+        }
+        let estimated_velocity = self.inertial_velocity.max(self.vertical_velocity);
+        if !self.mach_lockout && estimated_velocity >= self.mach_lockout_config.velocity_threshold {
+            self.mach_lockout = true;
+        } else if self.mach_lockout
+            && self.inertial_velocity < self.mach_lockout_config.release_velocity
+        {
+            self.mach_lockout = false;
+            // The barometric velocity window is full of readings from before the lockout, so
+            // start it over from the integrated velocity:
+            self.velocity_rolling_average.clear();
+            self.vertical_velocity = self.inertial_velocity;
+            self.last_velocity_calculation_packet = Some(data_packet.clone());
+        }
+    }
+
     pub fn get_processor_data_packet(&self) -> ProcessorDataPacket {
         ProcessorDataPacket {
             current_altitude: self.current_altitude,
             vertical_velocity: self.vertical_velocity,
             maximum_altitude: self.max_altitude,
             maximum_velocity: self.max_velocity,
+            acceleration: self.acceleration,
+            high_g_weight: self.high_g_weight,
+            mach_lockout: self.mach_lockout,
         }
     }
 
+    /// Blends the MPU6050 and high-g readings. Below the blend start only the (more precise)
+    /// MPU6050 is used, then we fade over to the high-g sensor as the MPU6050 approaches its
+    /// saturation limit, and use the high-g sensor alone once it saturates.
+    fn blend_acceleration(&self, data_packet: &IMUDataPacket) -> ([f32; 3], f32) {
+        let Some(high_g) = data_packet.high_g_acceleration else {
+            return (data_packet.acceleration, 0.0);
+        };
+
+        let peak = data_packet
+            .acceleration
+            .iter()
+            .fold(0.0_f32, |peak, a| peak.max(a.abs()));
+        let weight = if data_packet.accel_saturated || peak >= self.accel_saturation_limit {
+            1.0
+        } else if peak <= self.high_g_blend_start {
+            0.0
+        } else {
+            (peak - self.high_g_blend_start)
+                / (self.accel_saturation_limit - self.high_g_blend_start)
+        };
+
+        let blended = [0, 1, 2]
+            .map(|axis| data_packet.acceleration[axis] * (1.0 - weight) + high_g[axis] * weight);
+        (blended, weight)
+    }
+
     fn calculate_velocity_from_altitude(&mut self, data_packet: &IMUDataPacket) -> f32 {
         let last_altitude = self
             .last_velocity_calculation_packet
@@ -91,3 +209,153 @@ impl DataProcessor {
             / self.velocity_rolling_average.len() as f32
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HighGConfig;
+
+    /// A reading at `time_ms` with the barometer at `altitude` and the accelerometer measuring
+    /// `acceleration` along its z axis, which points up.
+    fn reading(time_ms: u64, altitude: f32, acceleration: f32) -> IMUDataPacket {
+        IMUDataPacket {
+            timestamp: time_ms * 1_000_000,
+            acceleration: [0.0, 0.0, acceleration],
+            gyro: [0.0; 3],
+            quaternion: [1.0, 0.0, 0.0, 0.0],
+            magnetic_field: [0.0; 3],
+            linear_acceleration: [0.0; 3],
+            calibration_status: [0; 4],
+            pressure_alt: altitude,
+            temperature: 20.0,
+            pressure: 101.325,
+            accel_saturated: false,
+            gyro_saturated: false,
+            high_g_acceleration: None,
+        }
+    }
+
+    #[test]
+    fn mach_lockout_keeps_the_maximum_altitude_barometric() {
+        let mut processor = DataProcessor::new(
+            &ImuConfig::default(),
+            &MachLockoutConfig {
+                enabled: true,
+                velocity_threshold: 20.0,
+                release_velocity: 10.0,
+            },
+        );
+        processor.first_update(&reading(0, 0.0, GRAVITY_METERS_PER_SECOND_SQUARED));
+
+        // Boost at 100 m/s^2 until the lockout engages:
+        let mut time_ms = 0;
+        while !processor.mach_lockout {
+            time_ms += 50;
+            assert!(time_ms < 2000, "lockout never engaged");
+            let altitude = processor.current_altitude + processor.vertical_velocity * 0.05;
+            processor.update(&reading(
+                time_ms,
+                altitude,
+                GRAVITY_METERS_PER_SECOND_SQUARED + 100.0,
+            ));
+        }
+        let max_at_entry = processor.max_altitude;
+
+        // The barometer reads garbage, and the dead-reckoned altitude climbs past the maximum
+        // without raising it:
+        for _ in 0..5 {
+            time_ms += 50;
+            processor.update(&reading(
+                time_ms,
+                10_000.0,
+                GRAVITY_METERS_PER_SECOND_SQUARED,
+            ));
+            assert!(processor.mach_lockout);
+            assert!(processor.current_altitude < 10_000.0);
+            assert_eq!(processor.max_altitude, max_at_entry);
+        }
+        assert!(processor.current_altitude > max_at_entry);
+
+        // Slowing down releases it, and the barometer takes over again:
+        while processor.mach_lockout {
+            time_ms += 50;
+            assert!(time_ms < 10_000, "lockout never released");
+            processor.update(&reading(
+                time_ms,
+                300.0,
+                GRAVITY_METERS_PER_SECOND_SQUARED - 100.0,
+            ));
+        }
+        assert_eq!(processor.current_altitude, 300.0);
+        assert_eq!(processor.max_altitude, 300.0);
+    }
+
+    /// A processor with a high-g accelerometer, and the MPU6050's blend start and saturation
+    /// limit.
+    fn high_g_processor() -> (DataProcessor, f32, f32) {
+        let config = ImuConfig {
+            high_g: Some(HighGConfig::default()),
+            ..ImuConfig::default()
+        };
+        let processor = DataProcessor::new(&config, &MachLockoutConfig::default());
+        let limit = accel_saturation_limit(&config);
+        (
+            processor,
+            limit * HighGConfig::default().blend_start_fraction,
+            limit,
+        )
+    }
+
+    fn with_high_g(acceleration: f32, high_g: f32) -> IMUDataPacket {
+        IMUDataPacket {
+            high_g_acceleration: Some([0.0, 0.0, high_g]),
+            ..reading(0, 0.0, acceleration)
+        }
+    }
+
+    #[test]
+    fn high_g_blend_below_the_blend_start() {
+        let (processor, blend_start, _) = high_g_processor();
+        let (acceleration, weight) =
+            processor.blend_acceleration(&with_high_g(blend_start - 1.0, blend_start + 5.0));
+        assert_eq!(weight, 0.0);
+        assert_eq!(acceleration, [0.0, 0.0, blend_start - 1.0]);
+    }
+
+    #[test]
+    fn high_g_blend_in_the_crossover() {
+        let (processor, blend_start, limit) = high_g_processor();
+        let midway = (blend_start + limit) / 2.0;
+        let (acceleration, weight) =
+            processor.blend_acceleration(&with_high_g(midway, midway + 10.0));
+        assert!((weight - 0.5).abs() < 1e-4, "{}", weight);
+        assert!((acceleration[2] - (midway + 5.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn high_g_blend_above_saturation() {
+        let (processor, _, limit) = high_g_processor();
+        let (acceleration, weight) = processor.blend_acceleration(&with_high_g(limit, 400.0));
+        assert_eq!(weight, 1.0);
+        assert_eq!(acceleration, [0.0, 0.0, 400.0]);
+
+        // A saturated reading can be anywhere, even below the limit:
+        let saturated = IMUDataPacket {
+            accel_saturated: true,
+            ..with_high_g(10.0, 400.0)
+        };
+        assert_eq!(
+            processor.blend_acceleration(&saturated),
+            ([0.0, 0.0, 400.0], 1.0)
+        );
+    }
+
+    #[test]
+    fn high_g_blend_without_a_high_g_reading() {
+        let (processor, _, limit) = high_g_processor();
+        assert_eq!(
+            processor.blend_acceleration(&reading(0, 0.0, limit)),
+            ([0.0, 0.0, limit], 0.0)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Leveled diagnostic messages, written to stderr, a file and/or syslog.
+//!
+//! Use the `trace!`, `debug!`, `info!`, `warn!` and `error!` macros. Until `init` is called,
+//! messages at `info` and above go to stderr. Warnings and errors from the same call site are
+//! rate-limited, so a sensor failing on every cycle doesn't flood the console.
+
+use crate::config::{DiagnosticsConfig, DiagnosticsSink, LogLevel};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+/// Lowest level that is kept, checked before taking the lock:
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+static DIAGNOSTICS: Mutex<Diagnostics> = Mutex::new(Diagnostics {
+    stderr: true,
+    file: None,
+    syslog: false,
+    rate_limit: Duration::from_millis(1000),
+    call_sites: BTreeMap::new(),
+});
+
+struct Diagnostics {
+    stderr: bool,
+    file: Option<BufWriter<File>>,
+    syslog: bool,
+    rate_limit: Duration,
+    /// Rate limiting state per call site (`file:line`):
+    call_sites: BTreeMap<&'static str, CallSite>,
+}
+
+struct CallSite {
+    last_written: Instant,
+    suppressed: u32,
+}
+
+impl LogLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "TRACE",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
+
+    fn syslog_priority(&self) -> libc::c_int {
+        match self {
+            LogLevel::Trace | LogLevel::Debug => libc::LOG_DEBUG,
+            LogLevel::Info => libc::LOG_INFO,
+            LogLevel::Warn => libc::LOG_WARNING,
+            LogLevel::Error => libc::LOG_ERR,
+        }
+    }
+}
+
+/// Sets up the sinks and level from the flight config.
+pub fn init(config: &DiagnosticsConfig) {
+    let mut diagnostics = DIAGNOSTICS.lock().unwrap_or_else(|e| e.into_inner());
+    diagnostics.stderr = config.sinks.contains(&DiagnosticsSink::Stderr);
+    diagnostics.syslog = config.sinks.contains(&DiagnosticsSink::Syslog);
+    diagnostics.rate_limit = Duration::from_millis(config.rate_limit_ms);
+    diagnostics.file = None;
+
+    if config.sinks.contains(&DiagnosticsSink::File) {
+        if let Some(parent) = Path::new(&config.file_path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.file_path)
+        {
+            Ok(file) => diagnostics.file = Some(BufWriter::new(file)),
+            Err(e) => {
+                // Don't lose messages if the file can't be opened:
+                eprintln!(
+                    "Failed to open diagnostics file {}: {}. Using stderr.",
+                    config.file_path, e
+                );
+                diagnostics.stderr = true;
+            }
+        }
+    }
+    if diagnostics.syslog {
+        // SAFETY: the identifier is a static C string, which syslog keeps a pointer to.
+        unsafe { libc::openlog(c"salt".as_ptr(), libc::LOG_PID, libc::LOG_USER) };
+    }
+    MIN_LEVEL.store(config.level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages at `level` are kept.
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 >= MIN_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Writes out anything buffered for the file sink.
+pub fn flush() {
+    let mut diagnostics = DIAGNOSTICS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = &mut diagnostics.file {
+        let _ = file.flush();
+    }
+}
+
+/// Used by the macros, which fill in the call site.
+#[doc(hidden)]
+pub fn log(level: LogLevel, location: &'static str, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let mut diagnostics = DIAGNOSTICS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut suppressed = 0;
+    if level >= LogLevel::Warn {
+        let now = Instant::now();
+        let rate_limit = diagnostics.rate_limit;
+        match diagnostics.call_sites.get_mut(location) {
+            Some(call_site) if now.duration_since(call_site.last_written) < rate_limit => {
+                call_site.suppressed += 1;
+                return;
+            }
+            Some(call_site) => {
+                suppressed = call_site.suppressed;
+                call_site.last_written = now;
+                call_site.suppressed = 0;
+            }
+            None => {
+                diagnostics.call_sites.insert(
+                    location,
+                    CallSite {
+                        last_written: now,
+                        suppressed: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    let message = if suppressed > 0 {
+        format!("{} ({} similar suppressed)", args, suppressed)
+    } else {
+        args.to_string()
+    };
+
+    if diagnostics.stderr {
+        eprintln!("{:5} {}: {}", level.name(), location, message);
+    }
+    if let Some(file) = &mut diagnostics.file {
+        let _ = writeln!(
+            file,
+            "{} {:5} {}: {}",
+            chrono::Utc::now().to_rfc3339(),
+            level.name(),
+            location,
+            message
+        );
+        // Warnings and errors are the ones we'd want after a crash:
+        if level >= LogLevel::Warn {
+            let _ = file.flush();
+        }
+    }
+    if diagnostics.syslog
+        && let Ok(message) = CString::new(format!("{}: {}", location, message))
+    {
+        // SAFETY: both strings are valid and NUL-terminated, and "%s" consumes exactly one.
+        unsafe { libc::syslog(level.syslog_priority(), c"%s".as_ptr(), message.as_ptr()) };
+    }
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::diagnostics::log($crate::config::LogLevel::Trace, concat!(file!(), ":", line!()), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::diagnostics::log($crate::config::LogLevel::Debug, concat!(file!(), ":", line!()), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::diagnostics::log($crate::config::LogLevel::Info, concat!(file!(), ":", line!()), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::diagnostics::log($crate::config::LogLevel::Warn, concat!(file!(), ":", line!()), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::diagnostics::log($crate::config::LogLevel::Error, concat!(file!(), ":", line!()), format_args!($($arg)+))
+    };
+}
@@ -0,0 +1,258 @@
+//! Structured events (state transitions, commands, sensor errors), written to their own log file
+//! and mirrored in telemetry, so we can reconstruct what happened and why.
+
+use crate::constants::SENSOR_RECOVERY_SAMPLES;
+use crate::{debug, error};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::SystemTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    StateTransition,
+    Command,
+    SensorError,
+    SensorRecovered,
+    MachLockout,
+    ChuteFailure,
+    PyroFire,
+    PyroStatus,
+}
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub timestamp: u64, // Nanoseconds since the UNIX epoch, same as the data log
+    pub severity: Severity,
+    pub kind: EventKind,
+    pub payload: String,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::StateTransition => "state_transition",
+            EventKind::Command => "command",
+            EventKind::SensorError => "sensor_error",
+            EventKind::SensorRecovered => "sensor_recovered",
+            EventKind::MachLockout => "mach_lockout",
+            EventKind::ChuteFailure => "chute_failure",
+            EventKind::PyroFire => "pyro_fire",
+            EventKind::PyroStatus => "pyro_status",
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {}: {}",
+            self.severity.name(),
+            self.kind.name(),
+            self.payload
+        )
+    }
+}
+
+/// Writes events to a csv file as they happen, and queues them up for the transmitter.
+pub struct EventLog {
+    writer: Option<BufWriter<File>>,
+    pending_telemetry: Vec<Event>,
+    /// Sensors currently failing, so we only log when that changes:
+    failing_sensors: BTreeMap<&'static str, FailingSensor>,
+}
+
+/// A sensor that failed to read and hasn't recovered yet.
+struct FailingSensor {
+    /// Good reads in a row since it last failed:
+    good_reads: u32,
+}
+
+impl EventLog {
+    /// Creates the event log at `path`. If that fails, events are still transmitted.
+    pub fn new(path: &str) -> Self {
+        let writer = match File::create(path) {
+            Ok(file) => {
+                let mut writer = BufWriter::new(file);
+                if let Err(e) = writeln!(writer, "timestamp,severity,kind,payload") {
+                    error!("Failed to write headers to event log: {}", e);
+                }
+                Some(writer)
+            }
+            Err(e) => {
+                error!("Failed to open event log {}: {}", path, e);
+                None
+            }
+        };
+
+        EventLog {
+            writer,
+            pending_telemetry: Vec::new(),
+            failing_sensors: BTreeMap::new(),
+        }
+    }
+
+    /// Records an event. Events are rare and important, so each one is flushed right away. They're
+    /// echoed to the diagnostics at debug level only: they have their own file and telemetry, and
+    /// below warnings nothing is rate-limited, so a burst of events would flood the console too.
+    pub fn record(&mut self, severity: Severity, kind: EventKind, payload: impl Into<String>) {
+        let event = Event {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64,
+            severity,
+            kind,
+            payload: payload.into(),
+        };
+        debug!("{}", event);
+
+        if let Some(writer) = &mut self.writer
+            && let Err(e) = write_event(writer, &event)
+        {
+            error!("Failed to write to event log: {}", e);
+        }
+        self.pending_telemetry.push(event);
+    }
+
+    /// Compares the errors from the latest sensor reads, as (sensor, error) pairs, with the ones
+    /// before. Records an error when a sensor starts failing and an info event once it has read
+    /// fine for `SENSOR_RECOVERY_SAMPLES` cycles in a row.
+    pub fn update_sensor_errors(&mut self, errors: &[(&'static str, String)]) {
+        let mut recovered = Vec::new();
+        for (sensor, failing) in self.failing_sensors.iter_mut() {
+            if errors.iter().any(|(name, _)| name == sensor) {
+                failing.good_reads = 0;
+            } else {
+                failing.good_reads += 1;
+                if failing.good_reads >= SENSOR_RECOVERY_SAMPLES {
+                    recovered.push(*sensor);
+                }
+            }
+        }
+        for sensor in recovered {
+            self.failing_sensors.remove(sensor);
+            self.record(Severity::Info, EventKind::SensorRecovered, sensor);
+        }
+
+        for (sensor, error) in errors {
+            if !self.failing_sensors.contains_key(sensor) {
+                self.record(
+                    Severity::Error,
+                    EventKind::SensorError,
+                    format!("{}: {}", sensor, error),
+                );
+                self.failing_sensors
+                    .insert(sensor, FailingSensor { good_reads: 0 });
+            }
+        }
+    }
+
+    /// Events recorded since the last call, to be transmitted.
+    pub fn take_pending_telemetry(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.pending_telemetry)
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+            writer.get_ref().sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_event(writer: &mut BufWriter<File>, event: &Event) -> io::Result<()> {
+    // The payload is free text, so quote it:
+    writeln!(
+        writer,
+        "{},{},{},\"{}\"",
+        event.timestamp,
+        event.severity.name(),
+        event.kind.name(),
+        event.payload.replace('"', "\"\"")
+    )?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An event log without a file, which only queues events for telemetry.
+    fn event_log() -> EventLog {
+        EventLog {
+            writer: None,
+            pending_telemetry: Vec::new(),
+            failing_sensors: BTreeMap::new(),
+        }
+    }
+
+    fn kinds(events: &[Event]) -> Vec<EventKind> {
+        events.iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn records_a_recovery_after_the_hold_off() {
+        let mut events = event_log();
+        let errors = [("BMP280 altitude", String::from("timeout"))];
+        events.update_sensor_errors(&errors);
+        events.update_sensor_errors(&errors);
+        assert_eq!(
+            kinds(&events.take_pending_telemetry()),
+            vec![EventKind::SensorError]
+        );
+
+        for _ in 1..SENSOR_RECOVERY_SAMPLES {
+            events.update_sensor_errors(&[]);
+        }
+        assert!(events.take_pending_telemetry().is_empty());
+        events.update_sensor_errors(&[]);
+        let recovered = events.take_pending_telemetry();
+        assert_eq!(kinds(&recovered), vec![EventKind::SensorRecovered]);
+        assert_eq!(recovered[0].payload, "BMP280 altitude");
+
+        // Failing again is a new error:
+        events.update_sensor_errors(&errors);
+        assert_eq!(
+            kinds(&events.take_pending_telemetry()),
+            vec![EventKind::SensorError]
+        );
+    }
+
+    #[test]
+    fn a_flapping_sensor_is_one_error() {
+        let mut events = event_log();
+        let errors = [("MPU6050 acceleration", String::from("I2C error"))];
+        for cycle in 0..1000 {
+            if cycle % 3 == 0 {
+                events.update_sensor_errors(&errors);
+            } else {
+                events.update_sensor_errors(&[]);
+            }
+        }
+        assert_eq!(
+            kinds(&events.take_pending_telemetry()),
+            vec![EventKind::SensorError]
+        );
+    }
+}
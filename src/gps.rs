@@ -0,0 +1,490 @@
+//! GPS receiver support. Parses NMEA (GGA and RMC) sentences and UBX NAV-PVT messages from a
+//! serial GPS module, so we know where the rocket is after landing.
+
+use crate::config::GpsConfig;
+use crate::debug;
+use serialport::{SerialPort, TTYPort};
+use std::error::Error;
+use std::io::Read;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug, Default)]
+pub struct GpsDataPacket {
+    pub timestamp: u64, // Time of the last position update, in nanoseconds since the epoch
+    pub latitude: f64,  // Degrees, north positive
+    pub longitude: f64, // Degrees, east positive
+    pub altitude: f32,  // Meters above mean sea level
+    pub fix_quality: u8, // As in NMEA GGA: 0 = no fix, 1 = GPS fix, 2 = differential fix, ...
+    pub satellites: u8, // Number of satellites used in the solution
+}
+
+impl GpsDataPacket {
+    pub fn has_fix(&self) -> bool {
+        self.fix_quality > 0
+    }
+}
+
+/// UBX frames start with these two bytes:
+const UBX_SYNC_1: u8 = 0xB5;
+const UBX_SYNC_2: u8 = 0x62;
+const UBX_CLASS_NAV: u8 = 0x01;
+const UBX_ID_NAV_PVT: u8 = 0x07;
+const UBX_NAV_PVT_LENGTH: usize = 92;
+/// Longest NMEA sentence allowed by the standard, including "$" and "\r\n":
+const MAX_NMEA_LENGTH: usize = 82;
+
+enum ParserState {
+    Idle,
+    Nmea(String),
+    /// Collecting a UBX frame. Holds the bytes after the two sync bytes.
+    Ubx(Vec<u8>),
+}
+
+/// Decodes a byte stream that may contain both NMEA sentences and UBX messages.
+pub struct GpsParser {
+    state: ParserState,
+    saw_ubx_sync_1: bool,
+}
+
+impl Default for GpsParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GpsParser {
+    pub fn new() -> Self {
+        GpsParser {
+            state: ParserState::Idle,
+            saw_ubx_sync_1: false,
+        }
+    }
+
+    /// Feeds bytes to the parser, updating `packet` with every complete message. Returns true if
+    /// any message updated the position.
+    pub fn push_bytes(&mut self, bytes: &[u8], packet: &mut GpsDataPacket) -> bool {
+        let mut updated = false;
+        for &byte in bytes {
+            updated |= self.push_byte(byte, packet);
+        }
+        updated
+    }
+
+    fn push_byte(&mut self, byte: u8, packet: &mut GpsDataPacket) -> bool {
+        match &mut self.state {
+            ParserState::Idle => {
+                if byte == b'$' {
+                    self.state = ParserState::Nmea(String::from("$"));
+                } else if self.saw_ubx_sync_1 && byte == UBX_SYNC_2 {
+                    self.state = ParserState::Ubx(Vec::with_capacity(UBX_NAV_PVT_LENGTH + 6));
+                }
+                self.saw_ubx_sync_1 = byte == UBX_SYNC_1;
+                false
+            }
+            ParserState::Nmea(sentence) => {
+                if byte == b'\n' {
+                    let sentence = std::mem::take(sentence);
+                    self.state = ParserState::Idle;
+                    parse_nmea(sentence.trim_end(), packet)
+                } else if !byte.is_ascii() || sentence.len() >= MAX_NMEA_LENGTH {
+                    // Corrupt or truncated sentence, drop it:
+                    self.state = ParserState::Idle;
+                    false
+                } else {
+                    sentence.push(byte as char);
+                    false
+                }
+            }
+            ParserState::Ubx(frame) => {
+                frame.push(byte);
+                // class, id and 2 length bytes come first, then the payload and 2 checksum bytes:
+                if frame.len() < 4 {
+                    return false;
+                }
+                let length = u16::from_le_bytes([frame[2], frame[3]]) as usize;
+                if length > UBX_NAV_PVT_LENGTH {
+                    // Not a message we care about, and we don't want to buffer it:
+                    self.state = ParserState::Idle;
+                    return false;
+                }
+                if frame.len() < length + 6 {
+                    return false;
+                }
+                let frame = std::mem::take(frame);
+                self.state = ParserState::Idle;
+                parse_ubx(&frame, packet)
+            }
+        }
+    }
+}
+
+/// Parses a single NMEA sentence such as "$GPGGA,...*47". Only GGA and RMC sentences from any
+/// talker (GP, GN, GL, ...) are used. Returns true if the packet was updated.
+pub fn parse_nmea(sentence: &str, packet: &mut GpsDataPacket) -> bool {
+    let Some(body) = sentence.strip_prefix('$') else {
+        return false;
+    };
+    let Some((body, checksum)) = body.rsplit_once('*') else {
+        return false;
+    };
+    let expected = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    if u8::from_str_radix(checksum, 16) != Ok(expected) {
+        return false;
+    }
+
+    let fields: Vec<&str> = body.split(',').collect();
+    // Line noise can hold anything, including multi-byte characters, so check before slicing:
+    if fields[0].len() != 5 || !fields[0].is_ascii() {
+        return false;
+    }
+    match &fields[0][2..] {
+        // $xxGGA,time,lat,N,lon,E,quality,satellites,hdop,altitude,M,...
+        "GGA" if fields.len() >= 10 => {
+            let quality = fields[6].parse().unwrap_or(0);
+            packet.fix_quality = quality;
+            packet.satellites = fields[7].parse().unwrap_or(0);
+            if quality == 0 {
+                return true;
+            }
+            let (Some(latitude), Some(longitude)) = (
+                parse_coordinate(fields[2], fields[3]),
+                parse_coordinate(fields[4], fields[5]),
+            ) else {
+                return true;
+            };
+            packet.latitude = latitude;
+            packet.longitude = longitude;
+            if let Ok(altitude) = fields[9].parse() {
+                packet.altitude = altitude;
+            }
+            packet.timestamp = now_nanos();
+            true
+        }
+        // $xxRMC,time,status,lat,N,lon,E,...
+        "RMC" if fields.len() >= 7 => {
+            if fields[2] != "A" {
+                return false;
+            }
+            let (Some(latitude), Some(longitude)) = (
+                parse_coordinate(fields[3], fields[4]),
+                parse_coordinate(fields[5], fields[6]),
+            ) else {
+                return false;
+            };
+            packet.latitude = latitude;
+            packet.longitude = longitude;
+            // RMC has no fix quality, but a valid RMC means we have at least a GPS fix:
+            packet.fix_quality = packet.fix_quality.max(1);
+            packet.timestamp = now_nanos();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Converts an NMEA "ddmm.mmmm" / "dddmm.mmmm" coordinate and hemisphere to signed degrees.
+fn parse_coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let dot = value.find('.').unwrap_or(value.len());
+    let minutes_start = dot.checked_sub(2)?;
+    let degrees: f64 = value.get(..minutes_start)?.parse().ok()?;
+    let minutes: f64 = value.get(minutes_start..)?.parse().ok()?;
+    let coordinate = degrees + minutes / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(coordinate),
+        "S" | "W" => Some(-coordinate),
+        _ => None,
+    }
+}
+
+/// Parses a UBX frame (without the sync bytes). Only NAV-PVT is used. Returns true if the packet
+/// was updated.
+pub fn parse_ubx(frame: &[u8], packet: &mut GpsDataPacket) -> bool {
+    if frame.len() < 6 {
+        return false;
+    }
+    let (message, checksum) = frame.split_at(frame.len() - 2);
+    // 8-bit Fletcher checksum over class, id, length and payload:
+    let (mut ck_a, mut ck_b) = (0u8, 0u8);
+    for &byte in message {
+        ck_a = ck_a.wrapping_add(byte);
+        ck_b = ck_b.wrapping_add(ck_a);
+    }
+    if checksum != [ck_a, ck_b] {
+        return false;
+    }
+
+    let (class, id, payload) = (message[0], message[1], &message[4..]);
+    if class != UBX_CLASS_NAV || id != UBX_ID_NAV_PVT || payload.len() != UBX_NAV_PVT_LENGTH {
+        return false;
+    }
+    let read_i32 = |offset: usize| {
+        i32::from_le_bytes([
+            payload[offset],
+            payload[offset + 1],
+            payload[offset + 2],
+            payload[offset + 3],
+        ])
+    };
+    let fix_type = payload[20];
+    let flags = payload[21];
+    let gnss_fix_ok = flags & 0x01 != 0;
+    let differential = flags & 0x02 != 0;
+
+    packet.satellites = payload[23];
+    // 2D and 3D fixes (and GNSS + dead reckoning) are usable; dead reckoning only and time only
+    // fixes are not:
+    packet.fix_quality = match (fix_type, gnss_fix_ok, differential) {
+        (2..=4, true, true) => 2,
+        (2..=4, true, false) => 1,
+        _ => 0,
+    };
+    if packet.fix_quality == 0 {
+        return true;
+    }
+    packet.longitude = read_i32(24) as f64 * 1e-7;
+    packet.latitude = read_i32(28) as f64 * 1e-7;
+    packet.altitude = read_i32(36) as f32 / 1000.0; // mm above mean sea level
+    packet.timestamp = now_nanos();
+    true
+}
+
+/// A GPS module connected to a serial port.
+pub struct Gps {
+    port: TTYPort,
+    parser: GpsParser,
+    gps_data_packet: GpsDataPacket,
+    read_error: Option<String>,
+}
+
+impl Gps {
+    pub fn new(config: &GpsConfig) -> Result<Self, Box<dyn Error>> {
+        let port = serialport::new(&config.port, config.baud_rate)
+            .timeout(Duration::from_millis(10))
+            .open_native()?;
+        Ok(Gps {
+            port,
+            parser: GpsParser::new(),
+            gps_data_packet: GpsDataPacket::default(),
+            read_error: None,
+        })
+    }
+
+    /// Reads whatever the GPS has sent since the last call, without blocking.
+    pub fn poll(&mut self) {
+        self.read_error = None;
+        let available = match self.port.bytes_to_read() {
+            Ok(0) => return,
+            Ok(available) => available as usize,
+            Err(e) => {
+                debug!("Failed to check GPS serial port: {}", e);
+                self.read_error = Some(e.to_string());
+                return;
+            }
+        };
+        let mut buffer = vec![0; available];
+        match self.port.read(&mut buffer) {
+            Ok(bytes_read) => {
+                self.parser
+                    .push_bytes(&buffer[..bytes_read], &mut self.gps_data_packet);
+            }
+            Err(e) => {
+                debug!("Failed to read from GPS: {}", e);
+                self.read_error = Some(e.to_string());
+            }
+        }
+    }
+
+    /// The error from the last `poll`, if it failed.
+    pub fn read_error(&self) -> Option<&str> {
+        self.read_error.as_deref()
+    }
+
+    /// Provides a clone of the most recent GPS data packet.
+    pub fn get_data_packet(&self) -> GpsDataPacket {
+        self.gps_data_packet.clone()
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `body` as a sentence, with its checksum.
+    fn sentence(body: &str) -> String {
+        format!("${}*{:02X}", body, body.bytes().fold(0u8, |acc, b| acc ^ b))
+    }
+
+    #[test]
+    fn parses_gga() {
+        let mut packet = GpsDataPacket::default();
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47";
+        assert!(parse_nmea(gga, &mut packet));
+        assert!((packet.latitude - 48.1173).abs() < 1e-6);
+        assert!((packet.longitude - 11.516_666).abs() < 1e-6);
+        assert_eq!(packet.altitude, 545.4);
+        assert_eq!(packet.fix_quality, 1);
+        assert_eq!(packet.satellites, 8);
+        assert!(packet.timestamp > 0);
+    }
+
+    #[test]
+    fn parses_gga_without_a_fix() {
+        let mut packet = GpsDataPacket::default();
+        assert!(parse_nmea(
+            &sentence("GNGGA,123519,,,,,0,03,,,M,,M,,"),
+            &mut packet
+        ));
+        assert!(!packet.has_fix());
+        assert_eq!(packet.satellites, 3);
+        assert_eq!(packet.timestamp, 0);
+    }
+
+    #[test]
+    fn parses_rmc() {
+        let mut packet = GpsDataPacket::default();
+        let rmc = "$GPRMC,123519,A,4807.038,S,01131.000,W,022.4,084.4,230394,003.1,W";
+        assert!(parse_nmea(&sentence(&rmc[1..]), &mut packet));
+        assert!((packet.latitude + 48.1173).abs() < 1e-6);
+        assert!((packet.longitude + 11.516_666).abs() < 1e-6);
+        assert_eq!(packet.fix_quality, 1);
+
+        // Void, so no position:
+        let mut packet = GpsDataPacket::default();
+        let void = "GPRMC,123519,V,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W";
+        assert!(!parse_nmea(&sentence(void), &mut packet));
+        assert_eq!(packet.latitude, 0.0);
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut packet = GpsDataPacket::default();
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48";
+        assert!(!parse_nmea(gga, &mut packet));
+        assert!(!packet.has_fix());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let mut packet = GpsDataPacket::default();
+        for garbage in [
+            "",
+            "$",
+            "$*",
+            "GPGGA,123519*47",
+            "$GPGGA,123519*zz",
+            // Replacement characters from a lossy decode, with a matching checksum:
+            &sentence("\u{FFFD}GA,1,2,3"),
+            &sentence("GPGGA,1,\u{FFFD}.5,N,4807.038,E,1,08,0.9,545.4,M"),
+            &sentence("GPXYZ,1,2,3"),
+            &sentence("GPGGA,1,2"),
+        ] {
+            parse_nmea(garbage, &mut packet);
+        }
+        assert_eq!(packet.latitude, 0.0);
+        assert_eq!(packet.longitude, 0.0);
+        assert_eq!(packet.timestamp, 0);
+    }
+
+    /// A NAV-PVT frame, sync bytes and checksum included, with the given fix type and flags, 12
+    /// satellites, and a 3D position in Munich.
+    fn nav_pvt(fix_type: u8, flags: u8) -> Vec<u8> {
+        let mut payload = vec![0u8; UBX_NAV_PVT_LENGTH];
+        payload[20] = fix_type;
+        payload[21] = flags;
+        payload[23] = 12;
+        payload[24..28].copy_from_slice(&115_166_666i32.to_le_bytes());
+        payload[28..32].copy_from_slice(&481_173_000i32.to_le_bytes());
+        payload[36..40].copy_from_slice(&545_400i32.to_le_bytes());
+
+        let mut message = vec![UBX_CLASS_NAV, UBX_ID_NAV_PVT];
+        message.extend_from_slice(&(UBX_NAV_PVT_LENGTH as u16).to_le_bytes());
+        message.extend_from_slice(&payload);
+        let (mut ck_a, mut ck_b) = (0u8, 0u8);
+        for &byte in &message {
+            ck_a = ck_a.wrapping_add(byte);
+            ck_b = ck_b.wrapping_add(ck_a);
+        }
+
+        let mut frame = vec![UBX_SYNC_1, UBX_SYNC_2];
+        frame.extend_from_slice(&message);
+        frame.extend_from_slice(&[ck_a, ck_b]);
+        frame
+    }
+
+    fn assert_munich(packet: &GpsDataPacket) {
+        assert!((packet.latitude - 48.1173).abs() < 1e-6);
+        assert!((packet.longitude - 11.516_666_6).abs() < 1e-6);
+        assert_eq!(packet.altitude, 545.4);
+        assert_eq!(packet.satellites, 12);
+        assert!(packet.timestamp > 0);
+    }
+
+    #[test]
+    fn parses_nav_pvt() {
+        let mut packet = GpsDataPacket::default();
+        assert!(parse_ubx(&nav_pvt(3, 0x01)[2..], &mut packet));
+        assert_munich(&packet);
+        assert_eq!(packet.fix_quality, 1);
+
+        let mut packet = GpsDataPacket::default();
+        assert!(parse_ubx(&nav_pvt(3, 0x03)[2..], &mut packet));
+        assert_eq!(packet.fix_quality, 2);
+    }
+
+    #[test]
+    fn parses_nav_pvt_without_a_fix() {
+        let mut packet = GpsDataPacket::default();
+        // A 3D fix without the gnssFixOK flag, and a time only fix:
+        for (fix_type, flags) in [(3, 0x00), (5, 0x01)] {
+            assert!(parse_ubx(&nav_pvt(fix_type, flags)[2..], &mut packet));
+            assert!(!packet.has_fix());
+            assert_eq!(packet.satellites, 12);
+            assert_eq!(packet.latitude, 0.0);
+            assert_eq!(packet.timestamp, 0);
+        }
+    }
+
+    #[test]
+    fn parses_a_frame_split_across_reads() {
+        let mut parser = GpsParser::new();
+        let mut packet = GpsDataPacket::default();
+        let frame = nav_pvt(3, 0x01);
+        // Split inside the sync bytes, the header and the payload:
+        let (first, rest) = frame.split_at(1);
+        let (second, rest) = rest.split_at(3);
+        let (third, fourth) = rest.split_at(40);
+        for chunk in [first, second, third] {
+            assert!(!parser.push_bytes(chunk, &mut packet));
+        }
+        assert!(parser.push_bytes(fourth, &mut packet));
+        assert_munich(&packet);
+
+        // NMEA right after a frame still parses:
+        let mut packet = GpsDataPacket::default();
+        let gga = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+        assert!(parser.push_bytes(gga.as_bytes(), &mut packet));
+        assert_eq!(packet.satellites, 8);
+    }
+
+    #[test]
+    fn drops_a_frame_with_a_bad_checksum() {
+        let mut parser = GpsParser::new();
+        let mut packet = GpsDataPacket::default();
+        let mut frame = nav_pvt(3, 0x01);
+        frame[40] ^= 0xFF;
+        assert!(!parser.push_bytes(&frame, &mut packet));
+        assert!(!packet.has_fix());
+        assert_eq!(packet.latitude, 0.0);
+
+        // The next good frame is parsed:
+        assert!(parser.push_bytes(&nav_pvt(3, 0x01), &mut packet));
+        assert_munich(&packet);
+    }
+}
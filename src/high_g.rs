@@ -0,0 +1,137 @@
+//! Drivers for the high-range accelerometers used during motor burn, when the MPU6050 saturates.
+
+use crate::config::{HighGConfig, HighGSensor};
+use crate::constants::GRAVITY_METERS_PER_SECOND_SQUARED;
+use embedded_hal::i2c::I2c;
+use linux_embedded_hal::I2cdev;
+use std::error::Error;
+
+/// A high-range accelerometer that reports acceleration in m/s^2.
+pub trait HighGAccelerometer {
+    fn read_acceleration(&mut self) -> Result<[f32; 3], Box<dyn Error>>;
+}
+
+/// Opens and initializes the configured high-g sensor on the given I2C bus.
+pub fn init_high_g(
+    path: &str,
+    config: &HighGConfig,
+) -> Result<Box<dyn HighGAccelerometer>, Box<dyn Error>> {
+    let i2c = I2cdev::new(path)?;
+    let sensor: Box<dyn HighGAccelerometer> = match config.sensor {
+        HighGSensor::Adxl375 => Box::new(Adxl375::new(i2c, config.address)?),
+        HighGSensor::H3lis331 => Box::new(H3lis331::new(i2c, config.address, config.range_g)?),
+    };
+    Ok(sensor)
+}
+
+/// Analog Devices ADXL375, fixed ±200 g range.
+pub struct Adxl375<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Adxl375<I>
+where
+    I::Error: Error + 'static,
+{
+    const DEVID: u8 = 0x00;
+    const BW_RATE: u8 = 0x2C;
+    const POWER_CTL: u8 = 0x2D;
+    const DATA_FORMAT: u8 = 0x31;
+    const DATAX0: u8 = 0x32;
+    const EXPECTED_DEVID: u8 = 0xE5;
+    /// Scale factor in g per LSB:
+    const SCALE_G_PER_LSB: f32 = 0.049;
+
+    pub fn new(mut i2c: I, address: u8) -> Result<Self, Box<dyn Error>> {
+        let mut id = [0u8];
+        i2c.write_read(address, &[Self::DEVID], &mut id)?;
+        if id[0] != Self::EXPECTED_DEVID {
+            return Err(format!("ADXL375 device ID mismatch: 0x{:02X}", id[0]).into());
+        }
+        // 200 Hz output rate, right-justified data with the required reserved bits set:
+        i2c.write(address, &[Self::BW_RATE, 0x0B])?;
+        i2c.write(address, &[Self::DATA_FORMAT, 0x0B])?;
+        // Start measuring:
+        i2c.write(address, &[Self::POWER_CTL, 0x08])?;
+        Ok(Adxl375 { i2c, address })
+    }
+}
+
+impl<I: I2c> HighGAccelerometer for Adxl375<I>
+where
+    I::Error: Error + 'static,
+{
+    fn read_acceleration(&mut self) -> Result<[f32; 3], Box<dyn Error>> {
+        let mut buf = [0u8; 6];
+        self.i2c
+            .write_read(self.address, &[Self::DATAX0], &mut buf)?;
+        Ok([0, 1, 2].map(|axis| {
+            let raw = i16::from_le_bytes([buf[axis * 2], buf[axis * 2 + 1]]);
+            raw as f32 * Self::SCALE_G_PER_LSB * GRAVITY_METERS_PER_SECOND_SQUARED
+        }))
+    }
+}
+
+/// STMicroelectronics H3LIS331DL, ±100/200/400 g range.
+pub struct H3lis331<I> {
+    i2c: I,
+    address: u8,
+    scale_g_per_lsb: f32,
+}
+
+impl<I: I2c> H3lis331<I>
+where
+    I::Error: Error + 'static,
+{
+    const WHO_AM_I: u8 = 0x0F;
+    const CTRL_REG1: u8 = 0x20;
+    const CTRL_REG4: u8 = 0x23;
+    const OUT_X_L: u8 = 0x28;
+    const EXPECTED_WHO_AM_I: u8 = 0x32;
+    /// Set on a register address to read several registers in one go:
+    const AUTO_INCREMENT: u8 = 0x80;
+
+    pub fn new(mut i2c: I, address: u8, range_g: u16) -> Result<Self, Box<dyn Error>> {
+        let mut id = [0u8];
+        i2c.write_read(address, &[Self::WHO_AM_I], &mut id)?;
+        if id[0] != Self::EXPECTED_WHO_AM_I {
+            return Err(format!("H3LIS331 WHO_AM_I mismatch: 0x{:02X}", id[0]).into());
+        }
+        // (FS bits, scale in g per LSB of the 12-bit reading):
+        let (full_scale, scale_g_per_lsb) = match range_g {
+            100 => (0b00, 0.049),
+            200 => (0b01, 0.098),
+            400 => (0b11, 0.195),
+            _ => return Err(format!("Invalid H3LIS331 range {} g", range_g).into()),
+        };
+        // Normal power mode, 100 Hz output rate, all axes enabled:
+        i2c.write(address, &[Self::CTRL_REG1, 0x2F])?;
+        // Block data update, so we never read the halves of two different samples:
+        i2c.write(address, &[Self::CTRL_REG4, 0x80 | (full_scale << 4)])?;
+        Ok(H3lis331 {
+            i2c,
+            address,
+            scale_g_per_lsb,
+        })
+    }
+}
+
+impl<I: I2c> HighGAccelerometer for H3lis331<I>
+where
+    I::Error: Error + 'static,
+{
+    fn read_acceleration(&mut self) -> Result<[f32; 3], Box<dyn Error>> {
+        let mut buf = [0u8; 6];
+        self.i2c.write_read(
+            self.address,
+            &[Self::OUT_X_L | Self::AUTO_INCREMENT],
+            &mut buf,
+        )?;
+        Ok([0, 1, 2].map(|axis| {
+            // 12-bit reading, left-justified:
+            let raw = i16::from_le_bytes([buf[axis * 2], buf[axis * 2 + 1]]) >> 4;
+            raw as f32 * self.scale_g_per_lsb * GRAVITY_METERS_PER_SECOND_SQUARED
+        }))
+    }
+}
@@ -1,3 +1,8 @@
+use crate::bno055::{Bno055, FUSION_ACCEL_RANGE_G, FUSION_GYRO_RANGE_DPS};
+use crate::config::{ImuConfig, ImuSensor};
+use crate::constants::GRAVITY_METERS_PER_SECOND_SQUARED;
+use crate::high_g::{HighGAccelerometer, init_high_g};
+use crate::{debug, info, warn};
 use bmp280::{Bmp280, Bmp280Builder};
 use linux_embedded_hal::{Delay, I2cdev};
 use mpu6050::*;
@@ -5,116 +10,111 @@ use mpu6050::device::{WHOAMI, AccelRange, GyroRange, ACCEL_HPF};
 use std::thread;
 use std::time::SystemTime;
 
+/// MPU6050 register holding the sample rate divider:
+const SMPLRT_DIV: u8 = 0x19;
+/// MPU6050 register holding the digital low-pass filter setting (DLPF_CFG, bits 2:0):
+const CONFIG: u8 = 0x1A;
+
 #[derive(Clone, Debug)]
 pub struct IMUDataPacket {
     pub timestamp: u64,
-    // From the BNO055 sensor:
-    pub acceleration: [f32; 3],   // array of (x, y, z) in m/s^2
-    pub quaternion: [f32; 4],     // array of (w, x, y, z)
-    pub magnetic_field: [f32; 3], // Magnetic field in microteslas
-    pub gyro: [f32; 3],           // rad/s
+    // From the MPU6050 or BNO055 sensor:
+    pub acceleration: [f32; 3], // array of (x, y, z) in m/s^2
+    pub gyro: [f32; 3],         // rad/s
+
+    // From the BNO055 sensor only:
+    pub quaternion: [f32; 4],          // array of (w, x, y, z)
+    pub magnetic_field: [f32; 3],      // Magnetic field in microteslas
+    pub linear_acceleration: [f32; 3], // Acceleration with gravity removed, in m/s^2
+    pub calibration_status: [u8; 4],   // (system, gyro, accel, mag), each from 0 to 3
 
     // From the BMP280 sensor:
     pub pressure_alt: f32, // Altitude derived from pressure
     pub temperature: f32,  // Temperature in Celsius
     pub pressure: f32,     // Pressure in Kilo Pascals
+
+    // Set when a reading is at the edge of the configured full-scale range:
+    pub accel_saturated: bool,
+    pub gyro_saturated: bool,
+
+    // From the optional high-g accelerometer, in m/s^2:
+    pub high_g_acceleration: Option<[f32; 3]>,
+}
+
+/// What was found while initializing the sensors, written at the start of the log.
+#[derive(Clone, Debug, Default)]
+pub struct SensorReport {
+    pub inertial_sensor: Option<String>, // e.g. "MPU6050 at 0x68 (manual init)"
+    pub high_g: Option<String>,          // e.g. "Adxl375 at 0x53"
+    pub ground_pressure: Option<f32>,    // Reference pressure for altitude, in kPa
+    pub calibration_status: Option<[u8; 4]>, // BNO055 (system, gyro, accel, mag) at startup
+}
+
+/// The sensor providing acceleration and rotation, selected in the flight config.
+enum InertialSensor {
+    Mpu6050(Mpu6050<I2cdev>),
+    Bno055(Bno055<I2cdev>),
 }
 
 pub struct IMU {
     bmp280: Bmp280,
-    mpu6050: Option<Mpu6050<I2cdev>>,
+    inertial_sensor: Option<InertialSensor>,
+    high_g: Option<Box<dyn HighGAccelerometer>>,
     imu_data_packet: IMUDataPacket,
+    accel_saturation_limit: f32, // m/s^2
+    gyro_saturation_limit: f32,  // rad/s
+    pub sensor_report: SensorReport,
+    /// (sensor, error) for each read that failed in the last `read_data`:
+    read_errors: Vec<(&'static str, String)>,
 }
 
 impl IMU {
-    pub fn new() -> Self {
+    pub fn new(config: &ImuConfig) -> Self {
         // Initialize sensors.
         let mut bmp280 = loop {
-            println!("Waiting for BMP280 sensor to be ready...");
+            info!("Waiting for BMP280 sensor to be ready...");
             if let Ok(dev) = Bmp280Builder::new().build() {
                 break dev;
             }
             thread::sleep(std::time::Duration::from_secs(1));
         };
-        println!("BMP280 sensor initialized.");
+        info!("BMP280 sensor initialized.");
         bmp280.zero().expect("Failed to reset pressure to zero");
         bmp280.zero().expect("Failed to reset pressure to zero");
-        // Try to create and initialize MPU6050; if anything fails keep None.
-        let mut delay = Delay;
-        let mpu6050 = match I2cdev::new("/dev/i2c-1") {
-            Ok(i2cdev) => {
-                let mut sensor = Mpu6050::new_with_addr(i2cdev, 0x68);
-                match sensor.init(&mut delay) {
-                    Ok(()) => {
-                        println!("MPU6050 initialized at address 0x68.");
-                        Some(sensor)
-                    }
-                    Err(Mpu6050Error::InvalidChipId(id)) => {
-                        eprintln!("MPU6050 WHO_AM_I mismatch at 0x68: 0x{:02X}. Trying manual init...", id);
-                        let manual_ok = sensor.set_sleep_enabled(false).is_ok()
-                            && { thread::sleep(std::time::Duration::from_millis(100)); true }
-                            && sensor.set_accel_range(AccelRange::G2).is_ok()
-                            && sensor.set_gyro_range(GyroRange::D250).is_ok()
-                            && sensor.set_accel_hpf(ACCEL_HPF::_RESET).is_ok();
-                        if manual_ok {
-                            if let Ok(whoami) = sensor.read_byte(WHOAMI) {
-                                eprintln!("Manual init succeeded; WHO_AM_I now 0x{:02X}", whoami);
-                            } else {
-                                eprintln!("Manual init succeeded; WHO_AM_I read failed");
-                            }
-                            Some(sensor)
-                        } else {
-                            eprintln!("Manual init failed at 0x68. Trying address 0x69...");
-                            match I2cdev::new("/dev/i2c-1") {
-                                Ok(i2cdev2) => {
-                                    let mut sensor2 = Mpu6050::new_with_addr(i2cdev2, 0x69);
-                                    match sensor2.init(&mut delay) {
-                                        Ok(()) => {
-                                            println!("MPU6050 initialized at address 0x69.");
-                                            Some(sensor2)
-                                        }
-                                        Err(err69) => {
-                                            eprintln!("MPU6050 init failed at 0x69: {:?}. Continuing without it.", err69);
-                                            None
-                                        }
-                                    }
-                                }
-                                Err(eopen2) => {
-                                    eprintln!("Failed to reopen /dev/i2c-1 for alt address: {:?}. Continuing without sensor.", eopen2);
-                                    None
-                                }
-                            }
-                        }
-                    }
-                    Err(err68) => {
-                        eprintln!("MPU6050 init failed at 0x68: {:?}", err68);
-                        match I2cdev::new("/dev/i2c-1") {
-                            Ok(i2cdev2) => {
-                                let mut sensor2 = Mpu6050::new_with_addr(i2cdev2, 0x69);
-                                match sensor2.init(&mut delay) {
-                                    Ok(()) => {
-                                        println!("MPU6050 initialized at address 0x69.");
-                                        Some(sensor2)
-                                    }
-                                    Err(err69) => {
-                                        eprintln!("MPU6050 init failed at 0x69: {:?}. Continuing without it.", err69);
-                                        None
-                                    }
-                                }
-                            }
-                            Err(eopen2) => {
-                                eprintln!("Failed to reopen /dev/i2c-1 for alt address: {:?}. Continuing without sensor.", eopen2);
-                                None
-                            }
-                        }
-                    }
+
+        let mut sensor_report = SensorReport {
+            ground_pressure: bmp280.pressure_kpa().ok(),
+            ..SensorReport::default()
+        };
+
+        let inertial_sensor = match config.sensor {
+            ImuSensor::Mpu6050 => init_mpu6050().map(|(mut sensor, description)| {
+                configure_mpu6050(&mut sensor, config);
+                sensor_report.inertial_sensor = Some(description.to_string());
+                InertialSensor::Mpu6050(sensor)
+            }),
+            ImuSensor::Bno055 => init_bno055(config).map(|mut sensor| {
+                sensor_report.inertial_sensor =
+                    Some(format!("BNO055 at 0x{:02X}", config.bno055_address));
+                sensor_report.calibration_status =
+                    sensor.read().ok().map(|reading| reading.calibration_status);
+                InertialSensor::Bno055(sensor)
+            }),
+        };
+
+        let high_g = config.high_g.as_ref().and_then(|high_g_config| {
+            match init_high_g("/dev/i2c-1", high_g_config) {
+                Ok(sensor) => {
+                    info!("{:?} high-g accelerometer initialized at address 0x{:02X}.", high_g_config.sensor, high_g_config.address);
+                    sensor_report.high_g = Some(format!("{:?} at 0x{:02X}", high_g_config.sensor, high_g_config.address));
+                    Some(sensor)
+                }
+                Err(e) => {
+                    warn!("Failed to initialize {:?} high-g accelerometer: {}. Continuing without it.", high_g_config.sensor, e);
+                    None
                 }
             }
-            Err(e) => {
-                eprintln!("Failed to open /dev/i2c-1 for MPU6050: {:?}. Continuing without sensor.", e);
-                None
-            }
-        };
+        });
 
         // The initial data packet is created directly.
         let initial_packet = IMUDataPacket {
@@ -126,11 +126,25 @@ impl IMU {
             pressure_alt: 0.0,
             temperature: 20.0,
             magnetic_field: [0.0, 0.0, 0.0],
+            linear_acceleration: [0.0, 0.0, 0.0],
+            calibration_status: [0, 0, 0, 0],
             gyro: [0.0, 0.0, 0.0],
             pressure: 101325.0, // Default pressure at sea level in Pascals
+            accel_saturated: false,
+            gyro_saturated: false,
+            high_g_acceleration: None,
         };
 
-        IMU { bmp280, mpu6050, imu_data_packet: initial_packet }
+        IMU {
+            bmp280,
+            inertial_sensor,
+            high_g,
+            imu_data_packet: initial_packet,
+            accel_saturation_limit: accel_saturation_limit(config),
+            gyro_saturation_limit: gyro_saturation_limit(config),
+            sensor_report,
+            read_errors: Vec::new(),
+        }
     }
 
     /// Provides a clone of the most recent IMU data packet.
@@ -139,45 +153,341 @@ impl IMU {
         self.imu_data_packet.clone()
     }
 
+    /// The sensors that failed to read in the last `read_data`, with their errors.
+    pub fn read_errors(&self) -> &[(&'static str, String)] {
+        &self.read_errors
+    }
+
     /// Reads new sensor data and updates the internal data packet.
     pub fn read_data(&mut self) {
         // Update fields directly on the struct's data packet.
-        // If a sensor read fails, the old value is kept.
-        if let Ok(altitude) = self.bmp280.altitude_m() {
-            self.imu_data_packet.pressure_alt = altitude;
-        } else {
-            eprintln!("Failed to read altitude from BMP280");
+        // If a sensor read fails, the old value is kept and the error is recorded.
+        self.read_errors.clear();
+        match self.bmp280.altitude_m() {
+            Ok(altitude) => self.imu_data_packet.pressure_alt = altitude,
+            Err(e) => {
+                debug!("Failed to read altitude from BMP280");
+                self.read_errors
+                    .push(("BMP280 altitude", format!("{:?}", e)));
+            }
         }
 
-        if let Ok(temp) = self.bmp280.temperature_celsius() {
-            self.imu_data_packet.temperature = temp;
-        } else {
-            eprintln!("Failed to read temperature from BMP280");
+        match self.bmp280.temperature_celsius() {
+            Ok(temp) => self.imu_data_packet.temperature = temp,
+            Err(e) => {
+                debug!("Failed to read temperature from BMP280");
+                self.read_errors
+                    .push(("BMP280 temperature", format!("{:?}", e)));
+            }
         }
 
-        if let Ok(pressure) = self.bmp280.pressure_kpa() {
-            self.imu_data_packet.pressure = pressure;
-        } else {
-            eprintln!("Failed to read pressure from BMP280");
+        match self.bmp280.pressure_kpa() {
+            Ok(pressure) => self.imu_data_packet.pressure = pressure,
+            Err(e) => {
+                debug!("Failed to read pressure from BMP280");
+                self.read_errors
+                    .push(("BMP280 pressure", format!("{:?}", e)));
+            }
         }
 
-        // Read from MPU6050 if available
-        if let Some(mpu) = self.mpu6050.as_mut() {
-            if let Ok(acc) = mpu.get_acc() {
-                self.imu_data_packet.acceleration = [acc.x, acc.y, acc.z];
-            } else {
-                eprintln!("Failed to read acceleration from MPU6050");
+        match self.inertial_sensor.as_mut() {
+            Some(InertialSensor::Mpu6050(mpu)) => {
+                // The driver reports acceleration in g, convert it to m/s^2:
+                match mpu.get_acc() {
+                    Ok(acc) => {
+                        self.imu_data_packet.acceleration =
+                            [acc.x, acc.y, acc.z].map(|a| a * GRAVITY_METERS_PER_SECOND_SQUARED);
+                    }
+                    Err(e) => {
+                        debug!("Failed to read acceleration from MPU6050");
+                        self.read_errors
+                            .push(("MPU6050 acceleration", format!("{:?}", e)));
+                    }
+                }
+                match mpu.get_gyro() {
+                    Ok(gyro) => self.imu_data_packet.gyro = [gyro.x, gyro.y, gyro.z],
+                    Err(e) => {
+                        debug!("Failed to read gyroscope from MPU6050");
+                        self.read_errors
+                            .push(("MPU6050 gyroscope", format!("{:?}", e)));
+                    }
+                }
             }
-            if let Ok(gyro) = mpu.get_gyro() {
-                self.imu_data_packet.gyro = [gyro.x, gyro.y, gyro.z];
-            } else {
-                eprintln!("Failed to read gyroscope from MPU6050");
+            Some(InertialSensor::Bno055(bno)) => match bno.read() {
+                Ok(reading) => {
+                    self.imu_data_packet.acceleration = reading.acceleration;
+                    self.imu_data_packet.gyro = reading.gyro;
+                    self.imu_data_packet.quaternion = reading.quaternion;
+                    self.imu_data_packet.magnetic_field = reading.magnetic_field;
+                    self.imu_data_packet.linear_acceleration = reading.linear_acceleration;
+                    self.imu_data_packet.calibration_status = reading.calibration_status;
+                }
+                Err(e) => {
+                    debug!("Failed to read from BNO055: {}", e);
+                    self.read_errors.push(("BNO055", e.to_string()));
+                }
+            },
+            // Failing to start is a failure to read on every cycle, so the safety checks see it:
+            None => self
+                .read_errors
+                .push(("inertial sensor", String::from("not found at startup"))),
+        }
+        self.imu_data_packet.accel_saturated = self
+            .imu_data_packet
+            .acceleration
+            .iter()
+            .any(|a| a.abs() >= self.accel_saturation_limit);
+        self.imu_data_packet.gyro_saturated = self
+            .imu_data_packet
+            .gyro
+            .iter()
+            .any(|g| g.abs() >= self.gyro_saturation_limit);
+
+        if let Some(high_g) = self.high_g.as_mut() {
+            match high_g.read_acceleration() {
+                Ok(acceleration) => self.imu_data_packet.high_g_acceleration = Some(acceleration),
+                Err(e) => {
+                    debug!(
+                        "Failed to read acceleration from high-g accelerometer: {}",
+                        e
+                    );
+                    self.read_errors
+                        .push(("high-g accelerometer", e.to_string()));
+                }
             }
         }
-        // Quaternion and magnetic field not available from MPU6050; keep previous values.
+        // Quaternion and magnetic field are only available from the BNO055; otherwise they keep
+        // their initial values.
         // Always update the timestamp to the time of the last read attempt.
         self.imu_data_packet.timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_nanos() as u64;
     }
 }
+
+/// Tries to initialize the MPU6050 at 0x68, then 0x69. Returns None if both fail, otherwise the
+/// sensor and a description of how it was found.
+fn init_mpu6050() -> Option<(Mpu6050<I2cdev>, &'static str)> {
+    // Try to create and initialize MPU6050; if anything fails keep None.
+    let mut delay = Delay;
+    match I2cdev::new("/dev/i2c-1") {
+        Ok(i2cdev) => {
+            let mut sensor = Mpu6050::new_with_addr(i2cdev, 0x68);
+            match sensor.init(&mut delay) {
+                Ok(()) => {
+                    info!("MPU6050 initialized at address 0x68.");
+                    Some((sensor, "MPU6050 at 0x68"))
+                }
+                Err(Mpu6050Error::InvalidChipId(id)) => {
+                    warn!(
+                        "MPU6050 WHO_AM_I mismatch at 0x68: 0x{:02X}. Trying manual init...",
+                        id
+                    );
+                    let manual_ok = sensor.set_sleep_enabled(false).is_ok()
+                        && {
+                            thread::sleep(std::time::Duration::from_millis(100));
+                            true
+                        }
+                        && sensor.set_accel_hpf(ACCEL_HPF::_RESET).is_ok();
+                    if manual_ok {
+                        if let Ok(whoami) = sensor.read_byte(WHOAMI) {
+                            warn!("Manual init succeeded; WHO_AM_I now 0x{:02X}", whoami);
+                        } else {
+                            warn!("Manual init succeeded; WHO_AM_I read failed");
+                        }
+                        Some((sensor, "MPU6050 at 0x68 (manual init)"))
+                    } else {
+                        warn!("Manual init failed at 0x68. Trying address 0x69...");
+                        match I2cdev::new("/dev/i2c-1") {
+                            Ok(i2cdev2) => {
+                                let mut sensor2 = Mpu6050::new_with_addr(i2cdev2, 0x69);
+                                match sensor2.init(&mut delay) {
+                                    Ok(()) => {
+                                        info!("MPU6050 initialized at address 0x69.");
+                                        Some((sensor2, "MPU6050 at 0x69"))
+                                    }
+                                    Err(err69) => {
+                                        warn!(
+                                            "MPU6050 init failed at 0x69: {:?}. Continuing without it.",
+                                            err69
+                                        );
+                                        None
+                                    }
+                                }
+                            }
+                            Err(eopen2) => {
+                                warn!(
+                                    "Failed to reopen /dev/i2c-1 for alt address: {:?}. Continuing without sensor.",
+                                    eopen2
+                                );
+                                None
+                            }
+                        }
+                    }
+                }
+                Err(err68) => {
+                    warn!("MPU6050 init failed at 0x68: {:?}", err68);
+                    match I2cdev::new("/dev/i2c-1") {
+                        Ok(i2cdev2) => {
+                            let mut sensor2 = Mpu6050::new_with_addr(i2cdev2, 0x69);
+                            match sensor2.init(&mut delay) {
+                                Ok(()) => {
+                                    info!("MPU6050 initialized at address 0x69.");
+                                    Some((sensor2, "MPU6050 at 0x69"))
+                                }
+                                Err(err69) => {
+                                    warn!(
+                                        "MPU6050 init failed at 0x69: {:?}. Continuing without it.",
+                                        err69
+                                    );
+                                    None
+                                }
+                            }
+                        }
+                        Err(eopen2) => {
+                            warn!(
+                                "Failed to reopen /dev/i2c-1 for alt address: {:?}. Continuing without sensor.",
+                                eopen2
+                            );
+                            None
+                        }
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            warn!(
+                "Failed to open /dev/i2c-1 for MPU6050: {:?}. Continuing without sensor.",
+                e
+            );
+            None
+        }
+    }
+}
+
+fn init_bno055(config: &ImuConfig) -> Option<Bno055<I2cdev>> {
+    let i2cdev = match I2cdev::new("/dev/i2c-1") {
+        Ok(i2cdev) => i2cdev,
+        Err(e) => {
+            warn!(
+                "Failed to open /dev/i2c-1 for BNO055: {:?}. Continuing without sensor.",
+                e
+            );
+            return None;
+        }
+    };
+    match Bno055::new(
+        i2cdev,
+        config.bno055_address,
+        config.bno055_external_crystal,
+    ) {
+        Ok(sensor) => {
+            info!(
+                "BNO055 initialized at address 0x{:02X}.",
+                config.bno055_address
+            );
+            Some(sensor)
+        }
+        Err(e) => {
+            warn!("BNO055 init failed: {}. Continuing without it.", e);
+            None
+        }
+    }
+}
+
+/// Acceleration in m/s^2 above which the reading on an axis is considered saturated.
+pub fn accel_saturation_limit(config: &ImuConfig) -> f32 {
+    let accel_range_g = match config.sensor {
+        ImuSensor::Mpu6050 => accel_range(config.accel_range_g).1,
+        // The range is fixed while the BNO055 runs sensor fusion:
+        ImuSensor::Bno055 => FUSION_ACCEL_RANGE_G,
+    };
+    accel_range_g * GRAVITY_METERS_PER_SECOND_SQUARED * config.saturation_threshold
+}
+
+/// Angular rate in rad/s above which the reading on an axis is considered saturated.
+fn gyro_saturation_limit(config: &ImuConfig) -> f32 {
+    let gyro_range_dps = match config.sensor {
+        ImuSensor::Mpu6050 => gyro_range(config.gyro_range_dps).1,
+        ImuSensor::Bno055 => FUSION_GYRO_RANGE_DPS,
+    };
+    gyro_range_dps.to_radians() * config.saturation_threshold
+}
+
+/// Applies the configured ranges, low-pass filter and sample rate to an initialized MPU6050.
+/// `init` always resets the ranges to their most sensitive settings, so this must run after it.
+fn configure_mpu6050(sensor: &mut Mpu6050<I2cdev>, config: &ImuConfig) {
+    let (accel, _) = accel_range(config.accel_range_g);
+    if let Err(e) = sensor.set_accel_range(accel) {
+        warn!("Failed to set MPU6050 accelerometer range: {:?}", e);
+    }
+
+    let (gyro, _) = gyro_range(config.gyro_range_dps);
+    if let Err(e) = sensor.set_gyro_range(gyro) {
+        warn!("Failed to set MPU6050 gyroscope range: {:?}", e);
+    }
+
+    if let Err(e) = sensor.write_byte(CONFIG, dlpf_config(config.dlpf_bandwidth_hz)) {
+        warn!("Failed to set MPU6050 low-pass filter: {:?}", e);
+    }
+
+    if let Err(e) = sensor.write_byte(SMPLRT_DIV, config.sample_rate_divider) {
+        warn!("Failed to set MPU6050 sample rate divider: {:?}", e);
+    }
+
+    info!(
+        "MPU6050 configured: ±{} g, ±{} dps, {} Hz DLPF, sample rate divider {}.",
+        config.accel_range_g,
+        config.gyro_range_dps,
+        config.dlpf_bandwidth_hz,
+        config.sample_rate_divider
+    );
+}
+
+/// Maps the configured accelerometer range to the driver setting and its full scale in g.
+fn accel_range(range_g: u8) -> (AccelRange, f32) {
+    match range_g {
+        2 => (AccelRange::G2, 2.0),
+        4 => (AccelRange::G4, 4.0),
+        8 => (AccelRange::G8, 8.0),
+        16 => (AccelRange::G16, 16.0),
+        _ => {
+            warn!("Invalid accelerometer range {} g, using 16 g.", range_g);
+            (AccelRange::G16, 16.0)
+        }
+    }
+}
+
+/// Maps the configured gyroscope range to the driver setting and its full scale in deg/s.
+fn gyro_range(range_dps: u16) -> (GyroRange, f32) {
+    match range_dps {
+        250 => (GyroRange::D250, 250.0),
+        500 => (GyroRange::D500, 500.0),
+        1000 => (GyroRange::D1000, 1000.0),
+        2000 => (GyroRange::D2000, 2000.0),
+        _ => {
+            warn!("Invalid gyroscope range {} dps, using 2000 dps.", range_dps);
+            (GyroRange::D2000, 2000.0)
+        }
+    }
+}
+
+/// Maps the configured low-pass filter bandwidth to the DLPF_CFG register value.
+fn dlpf_config(bandwidth_hz: u16) -> u8 {
+    match bandwidth_hz {
+        260 => 0,
+        184 => 1,
+        94 => 2,
+        44 => 3,
+        21 => 4,
+        10 => 5,
+        5 => 6,
+        _ => {
+            warn!(
+                "Invalid low-pass filter bandwidth {} Hz, using 44 Hz.",
+                bandwidth_hz
+            );
+            3
+        }
+    }
+}
@@ -1,7 +1,23 @@
+pub mod battery;
+pub mod binlog;
+pub mod bno055;
+pub mod camera;
+pub mod config;
 pub mod constants;
 pub mod context;
 pub mod data_processor;
+pub mod diagnostics;
+pub mod events;
+pub mod gps;
+pub mod high_g;
 pub mod imu;
+pub mod log_directory;
+pub mod log_schema;
 pub mod logger;
+pub mod metadata;
+pub mod pyro;
+pub mod safety;
 pub mod state;
+pub mod summary;
+pub mod transitions;
 pub mod transmitter;
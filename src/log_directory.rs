@@ -0,0 +1,159 @@
+//! Management of the log directory: flight numbering, free space checks and pruning of pad tests.
+//!
+//! Every file of a flight starts with `flight_<number>_`, e.g.
+//! `flight_0007_2025-01-01_12-00-00.csv` and `flight_0007_2025-01-01_12-00-00_summary.txt`.
+
+use crate::{info, warn};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Prefix of every file belonging to a flight:
+const FLIGHT_PREFIX: &str = "flight_";
+
+/// The flight number of a file in the log directory, if it belongs to a flight.
+fn flight_number(file_name: &str) -> Option<u32> {
+    file_name
+        .strip_prefix(FLIGHT_PREFIX)?
+        .split('_')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Number for the next flight, one more than the highest one in `directory`.
+pub fn next_flight_number(directory: &str) -> io::Result<u32> {
+    let mut highest = 0;
+    for entry in fs::read_dir(directory)? {
+        if let Some(number) = flight_number(&entry?.file_name().to_string_lossy()) {
+            highest = highest.max(number);
+        }
+    }
+    Ok(highest + 1)
+}
+
+/// Path of the new flight's files without their suffix, e.g. `logs/flight_0007_<date>`.
+pub fn flight_base_path(directory: &str, flight_number: u32) -> String {
+    format!(
+        "{}/{}{:04}_{}",
+        directory,
+        FLIGHT_PREFIX,
+        flight_number,
+        chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S")
+    )
+}
+
+/// Bytes available to us on the filesystem holding `directory`.
+pub fn free_space_bytes(directory: &str) -> io::Result<u64> {
+    let path = CString::new(Path::new(directory).as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stats = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is NUL-terminated and `stats` is only read if statvfs succeeded.
+    if unsafe { libc::statvfs(path.as_ptr(), stats.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stats = unsafe { stats.assume_init() };
+    // The field types differ between 32 and 64-bit targets:
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Deletes the files of old pad tests, keeping the `keep` most recent ones. A pad test is a flight
+/// that never launched, see `launched`.
+pub fn prune_pad_tests(directory: &str, keep: usize) -> io::Result<()> {
+    let mut flights: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for entry in fs::read_dir(directory)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if let Some(number) = flight_number(&file_name) {
+            flights.entry(number).or_default().push(file_name);
+        }
+    }
+
+    let pad_tests: Vec<&Vec<String>> = flights
+        .values()
+        .filter(|files| !launched(directory, files))
+        .collect();
+
+    let to_remove = pad_tests.len().saturating_sub(keep);
+    for files in &pad_tests[..to_remove] {
+        for file_name in files.iter() {
+            let path = format!("{}/{}", directory, file_name);
+            match fs::remove_file(&path) {
+                Ok(()) => info!("Removed pad test log {}.", path),
+                Err(e) => warn!("Failed to remove pad test log {}: {}", path, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether a flight's files show that it launched: its summary says so, or its event log has a
+/// transition to MotorBurn. Bench runs that were killed or lost power never write a summary, so a
+/// missing one doesn't count as a launch. An event log we can't read does, to be safe.
+fn launched(directory: &str, files: &[String]) -> bool {
+    files.iter().any(|file_name| {
+        let contents = || fs::read_to_string(format!("{}/{}", directory, file_name));
+        if file_name.ends_with("_summary.txt") {
+            contents().is_ok_and(|summary| summary.contains("launch_detected: true"))
+        } else if file_name.ends_with("_events.csv") {
+            contents().map_or(true, |events| events.contains("-> MotorBurn"))
+        } else {
+            false
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_flights_that_never_launched() {
+        let directory = std::env::temp_dir().join(format!("salt_prune_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let directory = directory.to_str().unwrap();
+        let write = |file_name: &str, contents: &str| {
+            fs::write(format!("{}/{}", directory, file_name), contents).unwrap();
+        };
+
+        // Killed on the bench, without a summary:
+        write("flight_0001_a.csv", "");
+        write(
+            "flight_0001_a_events.csv",
+            "1,info,state_transition,\"Standby -> Countdown\"\n",
+        );
+        // A flight that lost power before writing its summary:
+        write("flight_0002_b.csv", "");
+        write(
+            "flight_0002_b_events.csv",
+            "2,info,state_transition,\"Countdown -> MotorBurn\"\n",
+        );
+        // Pad tests with a summary:
+        write("flight_0003_c_summary.txt", "launch_detected: false\n");
+        write("flight_0004_d_summary.txt", "launch_detected: false\n");
+        // A flight with a summary:
+        write("flight_0005_e_summary.txt", "launch_detected: true\n");
+
+        prune_pad_tests(directory, 1).unwrap();
+        let mut remaining: Vec<String> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "flight_0002_b.csv",
+                "flight_0002_b_events.csv",
+                "flight_0004_d_summary.txt",
+                "flight_0005_e_summary.txt",
+            ]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
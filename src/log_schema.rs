@@ -0,0 +1,181 @@
+//! The fields written to the flight log, shared by the CSV and binary log formats.
+
+use crate::data_processor::ProcessorDataPacket;
+use crate::gps::GpsDataPacket;
+use crate::imu::IMUDataPacket;
+use std::fmt;
+
+/// How a field is stored. Values that aren't available (e.g. GPS without a GPS module) are
+/// logged as NaN, which the CSV format writes as an empty cell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FieldKind {
+    U64,
+    Char,
+    Bool,
+    U8,
+    F32,
+    F64,
+}
+
+impl FieldKind {
+    /// Size of a value in the binary log, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            FieldKind::U64 | FieldKind::F64 => 8,
+            FieldKind::F32 => 4,
+            FieldKind::Char | FieldKind::Bool | FieldKind::U8 => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogField {
+    pub name: String,
+    pub kind: FieldKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogValue {
+    U64(u64),
+    Char(char),
+    Bool(bool),
+    U8(u8),
+    F32(f32),
+    F64(f64),
+}
+
+impl LogValue {
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            LogValue::U64(v) => v as f64,
+            LogValue::Char(c) => c as u32 as f64,
+            LogValue::Bool(b) => b as u8 as f64,
+            LogValue::U8(v) => v as f64,
+            LogValue::F32(v) => v as f64,
+            LogValue::F64(v) => v,
+        }
+    }
+}
+
+impl fmt::Display for LogValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LogValue::U64(v) => write!(f, "{}", v),
+            LogValue::Char(c) => write!(f, "{}", c),
+            LogValue::Bool(b) => write!(f, "{}", b as u8),
+            LogValue::U8(v) => write!(f, "{}", v),
+            LogValue::F32(v) if v.is_nan() => Ok(()),
+            LogValue::F32(v) => write!(f, "{}", v),
+            LogValue::F64(v) if v.is_nan() => Ok(()),
+            LogValue::F64(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Every logged field, in column order. `build_record` must produce values in the same order.
+pub const LOG_FIELDS: [(&str, FieldKind); 43] = [
+    ("timestamp", FieldKind::U64),
+    ("state", FieldKind::Char),
+    ("accel_x", FieldKind::F32),
+    ("accel_y", FieldKind::F32),
+    ("accel_z", FieldKind::F32),
+    ("gyro_x", FieldKind::F32),
+    ("gyro_y", FieldKind::F32),
+    ("gyro_z", FieldKind::F32),
+    ("mag_x", FieldKind::F32),
+    ("mag_y", FieldKind::F32),
+    ("mag_z", FieldKind::F32),
+    ("quat_w", FieldKind::F32),
+    ("quat_x", FieldKind::F32),
+    ("quat_y", FieldKind::F32),
+    ("quat_z", FieldKind::F32),
+    ("pressure", FieldKind::F32),
+    ("altitude", FieldKind::F32),
+    ("max_altitude", FieldKind::F32),
+    ("velocity", FieldKind::F32),
+    ("max_velocity", FieldKind::F32),
+    ("temperature", FieldKind::F32),
+    ("accel_saturated", FieldKind::Bool),
+    ("gyro_saturated", FieldKind::Bool),
+    ("high_g_x", FieldKind::F32),
+    ("high_g_y", FieldKind::F32),
+    ("high_g_z", FieldKind::F32),
+    ("blended_accel_x", FieldKind::F32),
+    ("blended_accel_y", FieldKind::F32),
+    ("blended_accel_z", FieldKind::F32),
+    ("high_g_weight", FieldKind::F32),
+    ("mach_lockout", FieldKind::Bool),
+    ("linear_accel_x", FieldKind::F32),
+    ("linear_accel_y", FieldKind::F32),
+    ("linear_accel_z", FieldKind::F32),
+    ("calib_sys", FieldKind::U8),
+    ("calib_gyro", FieldKind::U8),
+    ("calib_accel", FieldKind::U8),
+    ("calib_mag", FieldKind::U8),
+    ("latitude", FieldKind::F64),
+    ("longitude", FieldKind::F64),
+    ("gps_altitude", FieldKind::F32),
+    ("gps_fix", FieldKind::U8),
+    ("gps_satellites", FieldKind::U8),
+];
+
+/// The log fields as owned `LogField`s.
+pub fn log_fields() -> Vec<LogField> {
+    LOG_FIELDS
+        .iter()
+        .map(|&(name, kind)| LogField {
+            name: name.to_string(),
+            kind,
+        })
+        .collect()
+}
+
+/// Collects the values of every field in `LOG_FIELDS` from the packets.
+pub fn build_record(
+    imu_data: &IMUDataPacket,
+    processor_data: &ProcessorDataPacket,
+    gps_data: Option<&GpsDataPacket>,
+    state: char,
+) -> Vec<LogValue> {
+    let high_g = imu_data.high_g_acceleration.unwrap_or([f32::NAN; 3]);
+    let mut record = Vec::with_capacity(LOG_FIELDS.len());
+    record.push(LogValue::U64(imu_data.timestamp));
+    record.push(LogValue::Char(state));
+    record.extend(imu_data.acceleration.map(LogValue::F32));
+    record.extend(imu_data.gyro.map(LogValue::F32));
+    record.extend(imu_data.magnetic_field.map(LogValue::F32));
+    record.extend(imu_data.quaternion.map(LogValue::F32));
+    record.push(LogValue::F32(imu_data.pressure));
+    record.push(LogValue::F32(processor_data.current_altitude));
+    record.push(LogValue::F32(processor_data.maximum_altitude));
+    record.push(LogValue::F32(processor_data.vertical_velocity));
+    record.push(LogValue::F32(processor_data.maximum_velocity));
+    record.push(LogValue::F32(imu_data.temperature));
+    record.push(LogValue::Bool(imu_data.accel_saturated));
+    record.push(LogValue::Bool(imu_data.gyro_saturated));
+    record.extend(high_g.map(LogValue::F32));
+    record.extend(processor_data.acceleration.map(LogValue::F32));
+    record.push(LogValue::F32(processor_data.high_g_weight));
+    record.push(LogValue::Bool(processor_data.mach_lockout));
+    record.extend(imu_data.linear_acceleration.map(LogValue::F32));
+    record.extend(imu_data.calibration_status.map(LogValue::U8));
+    match gps_data {
+        Some(gps) => {
+            record.push(LogValue::F64(gps.latitude));
+            record.push(LogValue::F64(gps.longitude));
+            record.push(LogValue::F32(gps.altitude));
+            record.push(LogValue::U8(gps.fix_quality));
+            record.push(LogValue::U8(gps.satellites));
+        }
+        None => {
+            record.push(LogValue::F64(f64::NAN));
+            record.push(LogValue::F64(f64::NAN));
+            record.push(LogValue::F32(f32::NAN));
+            // There's no NaN for integers, a missing GPS is simply "no fix":
+            record.push(LogValue::U8(0));
+            record.push(LogValue::U8(0));
+        }
+    }
+    debug_assert_eq!(record.len(), LOG_FIELDS.len());
+    record
+}
@@ -1,88 +1,440 @@
-//! This module will simply log the IMUDataPacket and ProcessorDataPacket to a file as a csv.
+//! This module logs the IMUDataPacket, ProcessorDataPacket and GpsDataPacket to a file, either as
+//! a csv or in the binary format from `binlog`. Extra outputs with their own rate and fields can be
+//! written alongside the main log.
 
+use crate::binlog::BinaryLogWriter;
+use crate::config::{LogFormat, LogOutputConfig, LoggingConfig};
+use crate::constants::MAIN_LOOP_INTERVAL_MS;
 use crate::data_processor::ProcessorDataPacket;
+use crate::gps::GpsDataPacket;
 use crate::imu::IMUDataPacket;
-use std::fs::OpenOptions;
+use crate::log_directory::{
+    flight_base_path, free_space_bytes, next_flight_number, prune_pad_tests,
+};
+use crate::log_schema::{LogField, LogValue, build_record, log_fields};
+use crate::{error, info, warn};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+/// A log file format.
+pub trait LogBackend {
+    fn write_record(&mut self, values: &[LogValue]) -> io::Result<()>;
+    fn flush(&mut self) -> io::Result<()>;
+    /// The underlying file, for syncing it to the SD card.
+    fn file(&self) -> &File;
+}
+
+pub struct CsvLogBackend {
+    writer: BufWriter<File>,
+}
+
+/// Writes the metadata as `# key: value` comment lines. Multi-line values start on the line after
+/// their key, indented.
+pub fn write_csv_metadata<W: Write>(
+    writer: &mut W,
+    metadata: &[(String, String)],
+) -> io::Result<()> {
+    for (key, value) in metadata {
+        if value.contains('\n') {
+            writeln!(writer, "# {}:", key)?;
+            for line in value.lines() {
+                writeln!(writer, "#   {}", line)?;
+            }
+        } else {
+            writeln!(writer, "# {}: {}", key, value)?;
+        }
+    }
+    Ok(())
+}
+
+impl CsvLogBackend {
+    pub fn new(file: File, fields: &[LogField], metadata: &[(String, String)]) -> io::Result<Self> {
+        let mut writer = BufWriter::new(file);
+        write_csv_metadata(&mut writer, metadata)?;
+        let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+        writeln!(writer, "{}", names.join(","))?;
+        Ok(CsvLogBackend { writer })
+    }
+}
+
+impl LogBackend for CsvLogBackend {
+    fn write_record(&mut self, values: &[LogValue]) -> io::Result<()> {
+        let line: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        writeln!(self.writer, "{}", line.join(","))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn file(&self) -> &File {
+        self.writer.get_ref()
+    }
+}
+
+pub struct BinaryLogBackend {
+    writer: BinaryLogWriter<BufWriter<File>>,
+}
+
+impl BinaryLogBackend {
+    pub fn new(file: File, fields: &[LogField], metadata: &[(String, String)]) -> io::Result<Self> {
+        Ok(BinaryLogBackend {
+            writer: BinaryLogWriter::new(BufWriter::new(file), fields, metadata)?,
+        })
+    }
+}
+
+impl LogBackend for BinaryLogBackend {
+    fn write_record(&mut self, values: &[LogValue]) -> io::Result<()> {
+        self.writer.write_record(values)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn file(&self) -> &File {
+        self.writer.get_ref().get_ref()
+    }
+}
+
+/// Opens `path` and writes the headers for `fields` in `format`.
+fn open_backend(
+    path: &str,
+    format: LogFormat,
+    fields: &[LogField],
+    metadata: &[(String, String)],
+) -> io::Result<Box<dyn LogBackend>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    Ok(match format {
+        LogFormat::Csv => Box::new(CsvLogBackend::new(file, fields, metadata)?),
+        LogFormat::Binary => Box::new(BinaryLogBackend::new(file, fields, metadata)?),
+    })
+}
+
+fn extension(format: LogFormat) -> &'static str {
+    match format {
+        LogFormat::Csv => "csv",
+        LogFormat::Binary => "bin",
+    }
+}
+
+/// Bytes needed for `records_per_second` records of `fields` over `seconds`.
+fn space_needed(
+    format: LogFormat,
+    fields: &[LogField],
+    records_per_second: u64,
+    seconds: u64,
+) -> u64 {
+    let record_size = match format {
+        // Rough width of a csv value, including the comma:
+        LogFormat::Csv => fields.len() as u64 * 12,
+        LogFormat::Binary => fields.iter().map(|field| field.kind.size() as u64).sum(),
+    };
+    record_size * records_per_second * seconds
+}
+
+/// An extra log, with a subset of the fields at a reduced rate.
+struct LogOutput {
+    backend: Box<dyn LogBackend>,
+    /// Indices of the logged fields in `LOG_FIELDS`:
+    field_indices: Vec<usize>,
+    /// Minimum nanoseconds between records:
+    interval: u64,
+    last_record: Option<u64>,
+}
+
+impl LogOutput {
+    /// Opens the output at `<base_path><suffix>.<extension>`. Unknown field names are skipped.
+    fn new(
+        config: &LogOutputConfig,
+        base_path: &str,
+        all_fields: &[LogField],
+        metadata: &[(String, String)],
+    ) -> io::Result<Self> {
+        let field_indices: Vec<usize> = if config.fields.is_empty() {
+            (0..all_fields.len()).collect()
+        } else {
+            config
+                .fields
+                .iter()
+                .filter_map(|name| {
+                    let index = all_fields.iter().position(|field| &field.name == name);
+                    if index.is_none() {
+                        warn!(
+                            "Unknown log field {} in output {}, skipping it.",
+                            name, config.suffix
+                        );
+                    }
+                    index
+                })
+                .collect()
+        };
+        let fields: Vec<LogField> = field_indices
+            .iter()
+            .map(|&index| all_fields[index].clone())
+            .collect();
+        let path = format!(
+            "{}{}.{}",
+            base_path,
+            config.suffix,
+            extension(config.format)
+        );
+
+        Ok(LogOutput {
+            backend: open_backend(&path, config.format, &fields, metadata)?,
+            field_indices,
+            interval: config.interval_ms * 1_000_000,
+            last_record: None,
+        })
+    }
+
+    fn write_record(&mut self, timestamp: u64, record: &[LogValue]) -> io::Result<()> {
+        let due = match self.last_record {
+            Some(last) => timestamp.saturating_sub(last) >= self.interval,
+            None => true,
+        };
+        if !due {
+            return Ok(());
+        }
+        self.last_record = Some(timestamp);
+        let values: Vec<LogValue> = self
+            .field_indices
+            .iter()
+            .map(|&index| record[index])
+            .collect();
+        self.backend.write_record(&values)
+    }
+}
 
 pub struct Logger {
-    writer: BufWriter<std::fs::File>,
+    backend: Box<dyn LogBackend>,
+    outputs: Vec<LogOutput>,
+    /// Path of the log without its extension, e.g. `logs/flight_0007_2025-01-01_12-00-00`:
+    base_path: String,
+    directory: String,
+    /// Space needed for a log of the longest expected flight, in bytes:
+    required_space: u64,
+    sync_interval: Duration,
+    last_sync: Instant,
+    /// Full-rate records from the last few seconds on the pad, as (timestamp, record). None once
+    /// we've launched:
+    pre_launch_buffer: Option<VecDeque<(u64, Vec<LogValue>)>>,
+    pre_launch_capacity: usize,
+    /// Nanoseconds between the pad records that are logged once they leave the buffer:
+    pad_log_interval: u64,
+    last_pad_record: Option<u64>,
 }
 
 impl Logger {
-    pub fn new() -> Self {
-        // File name is the date and time in the format YYYY-MM-DD_HH-MM-SS.csv (naive)
-        let file_path = format!(
-            "logs/{}.csv",
-            chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S")
-        );
+    /// Creates the log directory if needed and the log file, starting with `metadata` describing
+    /// the flight.
+    pub fn new(config: &LoggingConfig, metadata: &[(String, String)]) -> Self {
+        fs::create_dir_all(&config.directory).expect("Failed to create log directory");
+        if config.prune_pad_tests
+            && let Err(e) = prune_pad_tests(&config.directory, config.pad_tests_to_keep)
+        {
+            warn!("Failed to prune pad test logs: {}", e);
+        }
+
+        // File name is the flight number, then the date and time as YYYY-MM-DD_HH-MM-SS (naive)
+        let flight_number = next_flight_number(&config.directory).unwrap_or_else(|e| {
+            warn!(
+                "Failed to read the log directory for the flight number: {}",
+                e
+            );
+            0
+        });
+        let base_path = flight_base_path(&config.directory, flight_number);
+        let file_path = format!("{}.{}", base_path, extension(config.format));
 
-        let file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(file_path)
+        let fields = log_fields();
+        let backend = open_backend(&file_path, config.format, &fields, metadata)
             .expect("Failed to open log file");
 
-        let writer = BufWriter::new(file);
+        let records_per_second = 1000 / MAIN_LOOP_INTERVAL_MS;
+        let mut required_space = space_needed(
+            config.format,
+            &fields,
+            records_per_second,
+            config.max_flight_seconds,
+        );
 
-        let mut logger = Logger { writer };
-        logger
-            .write_headers()
-            .expect("Failed to write headers to log file");
-        logger
-    }
+        let mut outputs = Vec::new();
+        for output_config in &config.outputs {
+            match LogOutput::new(output_config, &base_path, &fields, metadata) {
+                Ok(output) => {
+                    let output_fields: Vec<LogField> = output
+                        .field_indices
+                        .iter()
+                        .map(|&index| fields[index].clone())
+                        .collect();
+                    let output_records_per_second = match output_config.interval_ms {
+                        0 => records_per_second,
+                        interval_ms => records_per_second.min(1000 / interval_ms.max(1)).max(1),
+                    };
+                    required_space += space_needed(
+                        output_config.format,
+                        &output_fields,
+                        output_records_per_second,
+                        config.max_flight_seconds,
+                    );
+                    outputs.push(output);
+                }
+                Err(e) => warn!("Failed to open log output {}: {}", output_config.suffix, e),
+            }
+        }
 
-    fn write_headers(&mut self) -> io::Result<()> {
-        writeln!(
-            self.writer,
-            "timestamp,state,accel_x,accel_y,accel_z,gyro_x,gyro_y,gyro_z,mag_x,mag_y,mag_z,quat_w,quat_x,quat_y,quat_z,pressure,altitude,max_altitude,velocity,max_velocity,temperature"
-        )?;
-        Ok(())
+        Logger {
+            backend,
+            outputs,
+            base_path,
+            directory: config.directory.clone(),
+            required_space,
+            sync_interval: Duration::from_millis(config.sync_interval_ms),
+            last_sync: Instant::now(),
+            pre_launch_buffer: Some(VecDeque::new()),
+            pre_launch_capacity: (config.pre_launch_buffer_seconds.max(0.0) * 1000.0
+                / MAIN_LOOP_INTERVAL_MS as f32) as usize,
+            pad_log_interval: config.pad_log_interval_ms * 1_000_000,
+            last_pad_record: None,
+        }
     }
 
     pub fn log_packets(
         &mut self,
         imu_data: &IMUDataPacket,
         processor_data: &ProcessorDataPacket,
-        state: &char
+        gps_data: Option<&GpsDataPacket>,
+        state: &char,
     ) -> () {
-        if let Err(e) = writeln!(
-            self.writer,
-            "{:?},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            imu_data.timestamp,
-            state,
-            imu_data.acceleration[0],
-            imu_data.acceleration[1],
-            imu_data.acceleration[2],
-            imu_data.gyro[0],
-            imu_data.gyro[1],
-            imu_data.gyro[2],
-            imu_data.magnetic_field[0],
-            imu_data.magnetic_field[1],
-            imu_data.magnetic_field[2],
-            imu_data.quaternion[0],
-            imu_data.quaternion[1],
-            imu_data.quaternion[2],
-            imu_data.quaternion[3],
-            imu_data.pressure,
-            processor_data.current_altitude,
-            processor_data.maximum_altitude,
-            processor_data.vertical_velocity,
-            processor_data.maximum_velocity,
-            imu_data.temperature
-        ) {
-            eprintln!("Failed to write to log file: {}", e);
+        let record = build_record(imu_data, processor_data, gps_data, *state);
+        for output in &mut self.outputs {
+            if let Err(e) = output.write_record(imu_data.timestamp, &record) {
+                error!("Failed to write to log output: {}", e);
+            }
+        }
+
+        match &mut self.pre_launch_buffer {
+            Some(buffer) => {
+                // On the pad, records only make it to the log at a reduced rate once they're too
+                // old for the buffer, so the log stays in order:
+                buffer.push_back((imu_data.timestamp, record));
+                while buffer.len() > self.pre_launch_capacity {
+                    let Some((timestamp, record)) = buffer.pop_front() else {
+                        break;
+                    };
+                    let due = match self.last_pad_record {
+                        Some(last) => timestamp.saturating_sub(last) >= self.pad_log_interval,
+                        None => true,
+                    };
+                    if due {
+                        self.last_pad_record = Some(timestamp);
+                        if let Err(e) = self.backend.write_record(&record) {
+                            error!("Failed to write to log file: {}", e);
+                        }
+                    }
+                }
+            }
+            None => {
+                if let Err(e) = self.backend.write_record(&record) {
+                    error!("Failed to write to log file: {}", e);
+                }
+            }
+        }
+
+        // Periodically push the data to the SD card, so a power loss only loses the last moments:
+        if self.last_sync.elapsed() >= self.sync_interval
+            && let Err(e) = self.sync()
+        {
+            error!("Failed to sync log file: {}", e);
         }
     }
 
+    /// Writes the whole pre-launch buffer to the log at full rate, and logs every record from now
+    /// on. Called at launch, or at shutdown if we never launched.
+    pub fn end_pad_logging(&mut self) {
+        let Some(buffer) = self.pre_launch_buffer.take() else {
+            return;
+        };
+        info!(
+            "Writing {} records from the pre-launch buffer to the log.",
+            buffer.len()
+        );
+        for (_, record) in buffer {
+            if let Err(e) = self.backend.write_record(&record) {
+                error!("Failed to write to log file: {}", e);
+            }
+        }
+    }
+
+    /// Flushes the main log, then the extra outputs. Only the main log's errors are returned, so a
+    /// failing output (e.g. a full USB stick) can't keep the flight log from being written.
     pub fn flush(&mut self) -> io::Result<()> {
-        self.writer.flush()
+        let result = self.backend.flush();
+        for output in &mut self.outputs {
+            if let Err(e) = output.backend.flush() {
+                error!("Failed to flush log output: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Flushes the logs and waits until they're written to the SD card, the main log first. As
+    /// with `flush`, errors from the extra outputs are only logged.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.last_sync = Instant::now();
+        let result = self
+            .backend
+            .flush()
+            .and_then(|()| self.backend.file().sync_data());
+        for output in &mut self.outputs {
+            if let Err(e) = output
+                .backend
+                .flush()
+                .and_then(|()| output.backend.file().sync_data())
+            {
+                error!("Failed to sync log output: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Checks that there's enough free space left for the longest expected flight, with a margin
+    /// for the other files of the flight. Returns why not otherwise.
+    pub fn check_free_space(&self) -> Result<(), String> {
+        let required = self.required_space * 2;
+        match free_space_bytes(&self.directory) {
+            Ok(free) if free >= required => Ok(()),
+            Ok(free) => Err(format!(
+                "only {} MB free in {}, {} MB needed",
+                free / 1_000_000,
+                self.directory,
+                required / 1_000_000
+            )),
+            Err(e) => Err(format!(
+                "failed to check free space in {}: {}",
+                self.directory, e
+            )),
+        }
+    }
+
+    /// Path for a file belonging to this flight, e.g. `logs/<date>_summary.txt` for the suffix
+    /// `_summary.txt`.
+    pub fn path_with_suffix(&self, suffix: &str) -> String {
+        format!("{}{}", self.base_path, suffix)
     }
 }
 
 impl Drop for Logger {
     fn drop(&mut self) {
-        let _ = self.writer.flush();
+        self.end_pad_logging();
+        let _ = self.flush();
     }
 }
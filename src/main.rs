@@ -1,15 +1,37 @@
 //! Main script for the Rust project.
 
+use salt::config::{CONFIG_FILE_PATH, FlightConfig};
+use salt::constants::MAIN_LOOP_INTERVAL_MS;
 use salt::context::Context;
+use salt::diagnostics;
+use salt::{info, trace};
+use std::process;
 use std::thread;
 
 fn main() {
-    let mut context = Context::new();
+    // Refuse to start on a broken config rather than fly with the defaults, which leave the pyros
+    // disabled:
+    let config = match FlightConfig::load(CONFIG_FILE_PATH) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load flight config {}", e);
+            process::exit(1);
+        }
+    };
+    diagnostics::init(&config.diagnostics);
+    let mut context = Context::new(config);
 
     // Wait for the rocket to be armed before starting the main loop:
-    println!("Waiting for rocket to be armed...");
+    info!("Waiting for rocket to be armed...");
     while !context.wait_for_boot_command() {
-        println!("waiting for boot command...");
+        trace!("waiting for boot command...");
+    }
+
+    // Start recording footage now that we're armed (unless it was an abort):
+    if let Some(camera) = &mut context.camera
+        && !context.state.is_finished()
+    {
+        camera.start(&context.logger.path_with_suffix(".h264"));
     }
 
     // Handle the first update:
@@ -18,12 +40,19 @@ fn main() {
         .data_processor
         .first_update(&context.imu.get_data_packet());
 
-    // Main loop
+    // Main loop, until we reach the Shutdown state:
     loop {
         context.update();
+        if context.is_shutdown() {
+            break;
+        }
 
         // Sleep for a short duration to avoid I2C flooding, and because the sensors have a max
         // update rate:
-        thread::sleep(std::time::Duration::from_millis(50));
+        thread::sleep(std::time::Duration::from_millis(MAIN_LOOP_INTERVAL_MS));
     }
+
+    info!("Shutting down...");
+    context.shutdown();
+    diagnostics::flush();
 }
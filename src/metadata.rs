@@ -0,0 +1,52 @@
+//! Metadata written at the start of every log, so a log still makes sense months later.
+
+use crate::config::FlightConfig;
+use crate::imu::SensorReport;
+
+/// Key-value pairs describing the software, configuration and sensors of this flight.
+pub fn flight_metadata(
+    config: &FlightConfig,
+    sensor_report: &SensorReport,
+) -> Vec<(String, String)> {
+    let not_found = || String::from("not found");
+    let calibration = match sensor_report.calibration_status {
+        Some([system, gyro, accel, mag]) => {
+            format!(
+                "BNO055 system {}, gyro {}, accel {}, mag {} (of 3)",
+                system, gyro, accel, mag
+            )
+        }
+        None => String::from("none (factory trim)"),
+    };
+
+    vec![
+        (
+            String::from("software_version"),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (String::from("git_hash"), env!("SALT_GIT_HASH").to_string()),
+        (String::from("start_time"), chrono::Utc::now().to_rfc3339()),
+        (
+            String::from("inertial_sensor"),
+            sensor_report
+                .inertial_sensor
+                .clone()
+                .unwrap_or_else(not_found),
+        ),
+        (
+            String::from("high_g_sensor"),
+            sensor_report
+                .high_g
+                .clone()
+                .unwrap_or_else(|| String::from("none")),
+        ),
+        (String::from("calibration"), calibration),
+        (
+            String::from("ground_pressure_kpa"),
+            sensor_report
+                .ground_pressure
+                .map_or_else(not_found, |pressure| pressure.to_string()),
+        ),
+        (String::from("config"), config.to_toml()),
+    ]
+}
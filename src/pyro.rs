@@ -0,0 +1,502 @@
+//! Pyro channels for deploying the parachutes: arming switch, continuity checks and timed firing.
+//!
+//! The GPIO pins are accessed through the `Gpio` trait, so the channels can be exercised on the
+//! bench (or a laptop) with `SimulatedGpio` instead of real charges.
+
+use crate::config::{GpioBackend, PyroChannelConfig, PyroConfig, PyroTrigger};
+use crate::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Digital inputs and outputs by pin number.
+pub trait Gpio {
+    /// Sets up `pin` as an output, driven low.
+    fn setup_output(&mut self, pin: u32) -> io::Result<()>;
+    /// Sets up `pin` as an input.
+    fn setup_input(&mut self, pin: u32) -> io::Result<()>;
+    fn write(&mut self, pin: u32, high: bool) -> io::Result<()>;
+    fn read(&mut self, pin: u32) -> io::Result<bool>;
+}
+
+/// Pins through the Linux sysfs interface (`/sys/class/gpio`). Pin numbers are the kernel's GPIO
+/// numbers, which on newer kernels are offset from the BCM numbers (see `/sys/kernel/debug/gpio`).
+pub struct SysfsGpio;
+
+impl SysfsGpio {
+    fn export(pin: u32, direction: &str) -> io::Result<()> {
+        let pin_path = format!("/sys/class/gpio/gpio{}", pin);
+        if !Path::new(&pin_path).exists() {
+            fs::write("/sys/class/gpio/export", pin.to_string())?;
+        }
+        fs::write(format!("{}/direction", pin_path), direction)
+    }
+}
+
+impl Gpio for SysfsGpio {
+    fn setup_output(&mut self, pin: u32) -> io::Result<()> {
+        // "low" sets the direction and drives the pin low in one go, so it never glitches high:
+        SysfsGpio::export(pin, "low")
+    }
+
+    fn setup_input(&mut self, pin: u32) -> io::Result<()> {
+        SysfsGpio::export(pin, "in")
+    }
+
+    fn write(&mut self, pin: u32, high: bool) -> io::Result<()> {
+        fs::write(
+            format!("/sys/class/gpio/gpio{}/value", pin),
+            if high { "1" } else { "0" },
+        )
+    }
+
+    fn read(&mut self, pin: u32) -> io::Result<bool> {
+        let value = fs::read_to_string(format!("/sys/class/gpio/gpio{}/value", pin))?;
+        Ok(value.trim() == "1")
+    }
+}
+
+/// Pins kept in memory. Inputs read high (armed, continuity) unless set otherwise or made to fail,
+/// and every output change is logged. Clones share the same pins, so a test can keep one to drive
+/// the inputs and check the outputs of the one given to `Pyro::with_gpio`.
+#[derive(Clone, Default)]
+pub struct SimulatedGpio {
+    pins: Arc<Mutex<SimulatedPins>>,
+}
+
+#[derive(Default)]
+struct SimulatedPins {
+    outputs: BTreeMap<u32, bool>,
+    inputs: BTreeMap<u32, bool>,
+    /// Inputs whose reads fail:
+    failing: BTreeSet<u32>,
+}
+
+impl SimulatedGpio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pins(&self) -> MutexGuard<'_, SimulatedPins> {
+        self.pins.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sets what `pin` reads as.
+    pub fn set_input(&self, pin: u32, high: bool) {
+        self.pins().inputs.insert(pin, high);
+    }
+
+    /// Makes reads of `pin` fail, or work again.
+    pub fn set_failing(&self, pin: u32, failing: bool) {
+        let mut pins = self.pins();
+        if failing {
+            pins.failing.insert(pin);
+        } else {
+            pins.failing.remove(&pin);
+        }
+    }
+
+    /// Whether `pin` is currently driven high.
+    pub fn output(&self, pin: u32) -> bool {
+        self.pins().outputs.get(&pin).copied().unwrap_or(false)
+    }
+}
+
+impl Gpio for SimulatedGpio {
+    fn setup_output(&mut self, pin: u32) -> io::Result<()> {
+        self.pins().outputs.insert(pin, false);
+        Ok(())
+    }
+
+    fn setup_input(&mut self, pin: u32) -> io::Result<()> {
+        self.pins().inputs.entry(pin).or_insert(true);
+        Ok(())
+    }
+
+    fn write(&mut self, pin: u32, high: bool) -> io::Result<()> {
+        info!(
+            "Simulated GPIO {} set {}.",
+            pin,
+            if high { "high" } else { "low" }
+        );
+        self.pins().outputs.insert(pin, high);
+        Ok(())
+    }
+
+    fn read(&mut self, pin: u32) -> io::Result<bool> {
+        let pins = self.pins();
+        if pins.failing.contains(&pin) {
+            return Err(io::Error::other(format!("simulated GPIO {} failed", pin)));
+        }
+        Ok(pins.inputs.get(&pin).copied().unwrap_or(true))
+    }
+}
+
+/// The outcome of trying to fire a channel, for the event log.
+pub struct FireAttempt {
+    pub channel: String,
+    pub result: Result<(), String>,
+}
+
+struct PyroChannel {
+    config: PyroChannelConfig,
+    /// When the output went high, while it's firing:
+    firing_since: Option<Instant>,
+    /// Set once its trigger was reached, until the output has been driven:
+    pending: bool,
+    /// Set once the output has been driven, after which the channel never fires again:
+    fired: bool,
+}
+
+pub struct Pyro {
+    gpio: Box<dyn Gpio>,
+    arming_pin: u32,
+    fire_duration: Duration,
+    channels: Vec<PyroChannel>,
+    /// Set by `disarm`, after which nothing fires whatever the arming switch says:
+    disarmed: bool,
+}
+
+impl Pyro {
+    /// Sets up the pins with the backend from the config, with every channel off.
+    pub fn new(config: &PyroConfig) -> io::Result<Self> {
+        let gpio: Box<dyn Gpio> = match config.backend {
+            GpioBackend::Sysfs => Box::new(SysfsGpio),
+            GpioBackend::Simulated => Box::new(SimulatedGpio::new()),
+        };
+        Pyro::with_gpio(config, gpio)
+    }
+
+    /// Sets up the pins on the given backend, with every channel off. Fails if a pin is used
+    /// more than once.
+    pub fn with_gpio(config: &PyroConfig, mut gpio: Box<dyn Gpio>) -> io::Result<Self> {
+        let mut pins = BTreeSet::from([config.arming_pin]);
+        for channel in &config.channels {
+            for pin in std::iter::once(channel.fire_pin).chain(channel.continuity_pin) {
+                if !pins.insert(pin) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "GPIO {} of pyro channel {} is already in use",
+                            pin, channel.name
+                        ),
+                    ));
+                }
+            }
+        }
+        gpio.setup_input(config.arming_pin)?;
+        for channel in &config.channels {
+            gpio.setup_output(channel.fire_pin)?;
+            if let Some(pin) = channel.continuity_pin {
+                gpio.setup_input(pin)?;
+            }
+        }
+        Ok(Pyro {
+            gpio,
+            arming_pin: config.arming_pin,
+            fire_duration: Duration::from_millis(config.fire_duration_ms),
+            channels: config
+                .channels
+                .iter()
+                .map(|channel| PyroChannel {
+                    config: channel.clone(),
+                    firing_since: None,
+                    pending: false,
+                    fired: false,
+                })
+                .collect(),
+            disarmed: false,
+        })
+    }
+
+    /// Whether the hardware arming switch is on. A failed read counts as disarmed.
+    pub fn is_armed(&mut self) -> bool {
+        match self.gpio.read(self.arming_pin) {
+            Ok(armed) => armed,
+            Err(e) => {
+                warn!("Failed to read the pyro arming switch: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Continuity of each channel with a sense pin, as (channel, continuity). A failed read counts
+    /// as no continuity.
+    pub fn continuity(&mut self) -> Vec<(String, bool)> {
+        let mut continuity = Vec::new();
+        for channel in &self.channels {
+            let Some(pin) = channel.config.continuity_pin else {
+                continue;
+            };
+            let closed = self.gpio.read(pin).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to read continuity of pyro channel {}: {}",
+                    channel.config.name, e
+                );
+                false
+            });
+            continuity.push((channel.config.name.clone(), closed));
+        }
+        continuity
+    }
+
+    /// Fires every channel for `trigger` that hasn't fired yet. Each one stays on for the fire
+    /// duration, turned off by `update`. A channel that couldn't fire stays pending, for `retry`.
+    pub fn fire(&mut self, trigger: PyroTrigger) -> Vec<FireAttempt> {
+        for channel in self.channels.iter_mut() {
+            if channel.config.trigger == trigger && !channel.fired {
+                channel.pending = true;
+            }
+        }
+        self.fire_pending(|channel| channel.config.trigger == trigger)
+    }
+
+    /// Tries again to fire the channels whose trigger was reached but didn't fire, e.g. because
+    /// the arming switch failed to read. Only returns the ones that fired: the failures were
+    /// already reported by `fire`.
+    pub fn retry(&mut self) -> Vec<FireAttempt> {
+        if self.disarmed || !self.channels.iter().any(|channel| channel.pending) {
+            return Vec::new();
+        }
+        let mut attempts = self.fire_pending(|_| true);
+        attempts.retain(|attempt| attempt.result.is_ok());
+        attempts
+    }
+
+    /// Tries to fire the pending channels that `select` picks.
+    fn fire_pending(&mut self, select: impl Fn(&PyroChannel) -> bool) -> Vec<FireAttempt> {
+        let armed = self.is_armed();
+        let mut attempts = Vec::new();
+        for channel in self.channels.iter_mut() {
+            if !channel.pending || !select(channel) {
+                continue;
+            }
+            let result = if self.disarmed {
+                Err(String::from("pyros are disarmed"))
+            } else if !armed {
+                Err(String::from("arming switch is off"))
+            } else {
+                match self.gpio.write(channel.config.fire_pin, true) {
+                    Ok(()) => {
+                        channel.firing_since = Some(Instant::now());
+                        channel.pending = false;
+                        channel.fired = true;
+                        Ok(())
+                    }
+                    Err(e) => Err(format!(
+                        "failed to set GPIO {}: {}",
+                        channel.config.fire_pin, e
+                    )),
+                }
+            };
+            attempts.push(FireAttempt {
+                channel: channel.config.name.clone(),
+                result,
+            });
+        }
+        attempts
+    }
+
+    /// Turns off the channels that have been firing for the fire duration. Call every cycle.
+    pub fn update(&mut self) {
+        for channel in self.channels.iter_mut() {
+            if let Some(since) = channel.firing_since
+                && since.elapsed() >= self.fire_duration
+            {
+                match self.gpio.write(channel.config.fire_pin, false) {
+                    Ok(()) => channel.firing_since = None,
+                    Err(e) => {
+                        warn!(
+                            "Failed to turn off pyro channel {}: {}",
+                            channel.config.name, e
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    /// Turns every channel off and keeps them off for the rest of the run.
+    pub fn disarm(&mut self) {
+        self.disarmed = true;
+        for channel in self.channels.iter_mut() {
+            channel.pending = false;
+            match self.gpio.write(channel.config.fire_pin, false) {
+                Ok(()) => channel.firing_since = None,
+                Err(e) => warn!(
+                    "Failed to turn off pyro channel {}: {}",
+                    channel.config.name, e
+                ),
+            }
+        }
+    }
+}
+
+impl Drop for Pyro {
+    fn drop(&mut self) {
+        // Never leave a channel on:
+        for channel in &self.channels {
+            let _ = self.gpio.write(channel.config.fire_pin, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const ARMING_PIN: u32 = 17;
+
+    fn pyro_config(fire_duration_ms: u64) -> PyroConfig {
+        PyroConfig {
+            backend: GpioBackend::Simulated,
+            arming_pin: ARMING_PIN,
+            fire_duration_ms,
+            channels: vec![
+                PyroChannelConfig {
+                    name: String::from("drogue"),
+                    fire_pin: 23,
+                    continuity_pin: Some(24),
+                    trigger: PyroTrigger::Apogee,
+                },
+                PyroChannelConfig {
+                    name: String::from("main"),
+                    fire_pin: 25,
+                    continuity_pin: Some(26),
+                    trigger: PyroTrigger::Main,
+                },
+            ],
+        }
+    }
+
+    /// A `Pyro` on simulated pins, and a handle to those pins.
+    fn simulated(fire_duration_ms: u64) -> (Pyro, SimulatedGpio) {
+        let gpio = SimulatedGpio::new();
+        let pyro = Pyro::with_gpio(&pyro_config(fire_duration_ms), Box::new(gpio.clone())).unwrap();
+        (pyro, gpio)
+    }
+
+    #[test]
+    fn does_not_fire_while_the_arming_switch_is_off() {
+        let (mut pyro, gpio) = simulated(1000);
+        gpio.set_input(ARMING_PIN, false);
+
+        let attempts = pyro.fire(PyroTrigger::Apogee);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(
+            attempts[0].result,
+            Err(String::from("arming switch is off"))
+        );
+        assert!(!gpio.output(23));
+    }
+
+    #[test]
+    fn fires_for_the_fire_duration() {
+        let (mut pyro, gpio) = simulated(50);
+
+        let attempts = pyro.fire(PyroTrigger::Apogee);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].channel, "drogue");
+        assert!(attempts[0].result.is_ok());
+        assert!(gpio.output(23));
+        assert!(!gpio.output(25));
+
+        pyro.update();
+        assert!(gpio.output(23));
+        thread::sleep(Duration::from_millis(60));
+        pyro.update();
+        assert!(!gpio.output(23));
+    }
+
+    #[test]
+    fn fires_each_channel_once() {
+        let (mut pyro, gpio) = simulated(0);
+
+        assert_eq!(pyro.fire(PyroTrigger::Main).len(), 1);
+        pyro.update();
+        assert!(!gpio.output(25));
+        assert!(pyro.fire(PyroTrigger::Main).is_empty());
+        assert!(!gpio.output(25));
+    }
+
+    #[test]
+    fn retries_after_the_arming_switch_fails_to_read() {
+        let (mut pyro, gpio) = simulated(1000);
+        gpio.set_failing(ARMING_PIN, true);
+
+        let attempts = pyro.fire(PyroTrigger::Apogee);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(
+            attempts[0].result,
+            Err(String::from("arming switch is off"))
+        );
+        assert!(!gpio.output(23));
+        assert!(pyro.retry().is_empty());
+        assert!(!gpio.output(23));
+
+        // Once the switch reads again, the channel fires, only once:
+        gpio.set_failing(ARMING_PIN, false);
+        let attempts = pyro.retry();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].channel, "drogue");
+        assert!(attempts[0].result.is_ok());
+        assert!(gpio.output(23));
+        assert!(!gpio.output(25));
+        assert!(pyro.retry().is_empty());
+        assert!(pyro.fire(PyroTrigger::Apogee).is_empty());
+    }
+
+    #[test]
+    fn disarm_turns_channels_off_and_refuses_to_fire() {
+        let (mut pyro, gpio) = simulated(1000);
+
+        pyro.fire(PyroTrigger::Apogee);
+        assert!(gpio.output(23));
+        pyro.disarm();
+        assert!(!gpio.output(23));
+
+        let attempts = pyro.fire(PyroTrigger::Main);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].result, Err(String::from("pyros are disarmed")));
+        assert!(!gpio.output(25));
+        assert!(pyro.retry().is_empty());
+        assert!(!gpio.output(25));
+    }
+
+    #[test]
+    fn reads_continuity() {
+        let (mut pyro, gpio) = simulated(1000);
+        gpio.set_input(26, false);
+
+        assert_eq!(
+            pyro.continuity(),
+            vec![
+                (String::from("drogue"), true),
+                (String::from("main"), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_pins_used_twice() {
+        let mut config = pyro_config(1000);
+        config.channels[1].continuity_pin = Some(23);
+        assert!(Pyro::with_gpio(&config, Box::new(SimulatedGpio::new())).is_err());
+
+        let mut config = pyro_config(1000);
+        config.channels[0].fire_pin = ARMING_PIN;
+        assert!(Pyro::with_gpio(&config, Box::new(SimulatedGpio::new())).is_err());
+    }
+
+    #[test]
+    fn requires_channel_fields() {
+        let channel = "name = \"main\"\nfire_pin = 25\n";
+        assert!(toml::from_str::<PyroChannelConfig>(channel).is_err());
+        let channel = "name = \"main\"\nfire_pin = 25\ntriger = \"main\"\ntrigger = \"main\"\n";
+        assert!(toml::from_str::<PyroChannelConfig>(channel).is_err());
+    }
+}
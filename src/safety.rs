@@ -0,0 +1,180 @@
+//! Internal safety checks that take the rocket to the Abort or Fault state before launch: a
+//! flight-critical sensor failing to read, the rocket tilting or the battery running low.
+
+use crate::config::SafetyConfig;
+use std::time::{Duration, Instant};
+
+/// Sensors we can't fly without: barometric altitude and acceleration. Others, like the
+/// temperature or the high-g accelerometer, only degrade the data. "inertial sensor" is the one
+/// that wasn't found at startup.
+const CRITICAL_SENSORS: &[&str] = &[
+    "BMP280 altitude",
+    "MPU6050 acceleration",
+    "BNO055",
+    "inertial sensor",
+];
+
+/// What a safety check decided.
+pub enum SafetyTrip {
+    /// Launching isn't safe, but the flight computer is fine.
+    Abort(String),
+    /// The flight computer can't be trusted anymore.
+    Fault(String),
+}
+
+pub struct SafetyMonitor {
+    max_pad_tilt_degrees: f32,
+    min_battery_voltage: Option<f32>,
+    trip_delay: Duration,
+    /// When each condition started, while it lasts:
+    sensor_failing_since: Option<Instant>,
+    tilted_since: Option<Instant>,
+    low_battery_since: Option<Instant>,
+}
+
+impl SafetyMonitor {
+    pub fn new(config: &SafetyConfig) -> Self {
+        SafetyMonitor {
+            max_pad_tilt_degrees: config.max_pad_tilt_degrees,
+            min_battery_voltage: config.min_battery_voltage,
+            trip_delay: Duration::from_millis(config.trip_delay_ms),
+            sensor_failing_since: None,
+            tilted_since: None,
+            low_battery_since: None,
+        }
+    }
+
+    /// Checks this cycle's readings. The tilt and battery are only checked on the pad, where
+    /// stopping is still an option; the battery voltage is None if it wasn't read.
+    pub fn check(
+        &mut self,
+        on_pad: bool,
+        imu_errors: &[(&'static str, String)],
+        acceleration: [f32; 3],
+        battery_voltage: Option<f32>,
+    ) -> Option<SafetyTrip> {
+        let mut sensors: Vec<&str> = imu_errors
+            .iter()
+            .map(|(sensor, _)| *sensor)
+            .filter(|sensor| CRITICAL_SENSORS.contains(sensor))
+            .collect();
+        // A working accelerometer always measures gravity and some noise. Exact zeros are one
+        // that never produced a reading, which would also hide any tilt:
+        if acceleration == [0.0; 3] {
+            sensors.push("acceleration (all zeros)");
+        }
+        if let Some(since) = track(&mut self.sensor_failing_since, !sensors.is_empty())
+            && since.elapsed() >= self.trip_delay
+        {
+            return Some(SafetyTrip::Fault(format!(
+                "{} failed to read for {} ms",
+                sensors.join(", "),
+                self.trip_delay.as_millis()
+            )));
+        }
+
+        let tilt = tilt_degrees(acceleration);
+        if let Some(since) = track(
+            &mut self.tilted_since,
+            on_pad && tilt > self.max_pad_tilt_degrees,
+        ) && since.elapsed() >= self.trip_delay
+        {
+            return Some(SafetyTrip::Abort(format!(
+                "tilted {:.1} degrees on the pad, more than the limit of {:.1}",
+                tilt, self.max_pad_tilt_degrees
+            )));
+        }
+
+        let low_battery = match (self.min_battery_voltage, battery_voltage) {
+            (Some(min_voltage), Some(voltage)) => on_pad && voltage < min_voltage,
+            _ => false,
+        };
+        if let Some(since) = track(&mut self.low_battery_since, low_battery)
+            && since.elapsed() >= self.trip_delay
+        {
+            return Some(SafetyTrip::Abort(format!(
+                "battery at {:.2} V, below the minimum of {:.2} V",
+                battery_voltage.unwrap_or_default(),
+                self.min_battery_voltage.unwrap_or_default()
+            )));
+        }
+        None
+    }
+}
+
+/// Starts or clears the timer for a condition, and returns when it started if it's still going.
+fn track(since: &mut Option<Instant>, condition: bool) -> Option<Instant> {
+    if condition {
+        Some(*since.get_or_insert_with(Instant::now))
+    } else {
+        *since = None;
+        None
+    }
+}
+
+/// Angle between the measured acceleration and the sensor's z axis. At rest the accelerometer
+/// measures gravity, so this is how far the rocket leans from vertical.
+fn tilt_degrees(acceleration: [f32; 3]) -> f32 {
+    let magnitude = acceleration.iter().map(|a| a * a).sum::<f32>().sqrt();
+    if magnitude == 0.0 {
+        return 0.0;
+    }
+    (acceleration[2] / magnitude)
+        .clamp(-1.0, 1.0)
+        .acos()
+        .to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::GRAVITY_METERS_PER_SECOND_SQUARED;
+
+    const UPRIGHT: [f32; 3] = [0.0, 0.0, GRAVITY_METERS_PER_SECOND_SQUARED];
+
+    /// A monitor that acts on the first cycle a condition is seen.
+    fn monitor() -> SafetyMonitor {
+        SafetyMonitor::new(&SafetyConfig {
+            trip_delay_ms: 0,
+            ..SafetyConfig::default()
+        })
+    }
+
+    #[test]
+    fn upright_on_the_pad_is_fine() {
+        assert!(monitor().check(true, &[], UPRIGHT, None).is_none());
+    }
+
+    #[test]
+    fn a_missing_inertial_sensor_faults() {
+        let errors = [("inertial sensor", String::from("not found at startup"))];
+        let trip = monitor().check(false, &errors, [0.0; 3], None);
+        let Some(SafetyTrip::Fault(reason)) = trip else {
+            panic!("missing inertial sensor didn't fault");
+        };
+        assert!(reason.contains("inertial sensor"), "{}", reason);
+    }
+
+    #[test]
+    fn all_zero_acceleration_faults() {
+        let trip = monitor().check(true, &[], [0.0; 3], None);
+        assert!(matches!(trip, Some(SafetyTrip::Fault(_))));
+    }
+
+    #[test]
+    fn a_non_critical_sensor_failing_is_fine() {
+        let errors = [("BMP280 temperature", String::from("timeout"))];
+        assert!(monitor().check(true, &errors, UPRIGHT, None).is_none());
+    }
+
+    #[test]
+    fn tilt_on_the_pad_aborts() {
+        let tilted = [GRAVITY_METERS_PER_SECOND_SQUARED, 0.0, 0.0];
+        assert!(matches!(
+            monitor().check(true, &[], tilted, None),
+            Some(SafetyTrip::Abort(_))
+        ));
+        // Before the countdown, the rocket may still be lying down:
+        assert!(monitor().check(false, &[], tilted, None).is_none());
+    }
+}
@@ -1,15 +1,24 @@
+use crate::config::DeploymentConfig;
 use crate::constants::{
-    GROUND_ALTITUDE_METERS, MAX_ALTITUDE_THRESHOLD, MAX_FREE_FALL_SECONDS,
-    SECONDS_TO_CONSIDERED_LANDED,
+    APOGEE_ALTITUDE_DROP_METERS, APOGEE_TIMER_MARGIN_SECONDS, APOGEE_VELOCITY_SAMPLES,
+    APOGEE_VOTES_REQUIRED, BURNOUT_ACCELERATION_METERS_PER_SECOND_SQUARED, BURNOUT_SAMPLES,
+    CHUTE_DEPLOY_GRACE_SECONDS, DESCENT_RATE_SAMPLES, GRAVITY_METERS_PER_SECOND_SQUARED,
+    GROUND_ALTITUDE_METERS, LANDED_ACCELERATION_STDDEV_METERS_PER_SECOND_SQUARED,
+    LANDED_ALTITUDE_RANGE_METERS, LANDED_VELOCITY_METERS_PER_SECOND, LANDED_WINDOW_SAMPLES,
+    LAUNCH_ACCELERATION_METERS_PER_SECOND_SQUARED, LAUNCH_ACCELERATION_SAMPLES,
+    LAUNCH_ALTITUDE_GAIN_METERS, LAUNCH_CONFIRMATION_SECONDS, MAX_COAST_SECONDS,
+    MAX_DESCENT_SECONDS, MAX_VELOCITY_THRESHOLD, SECONDS_TO_CONSIDERED_LANDED,
+    TAKEOFF_VELOCITY_METERS_PER_SECOND,
 };
-use crate::context::Context;
 use crate::data_processor::ProcessorDataPacket;
+use crate::transitions::GuardInputs;
+use std::collections::VecDeque;
 
+/// Per-state tracking. When to leave a state is decided by the guards in `transitions`, which read
+/// what the states track here.
 pub trait State {
     /// Used for updating the struct with new data if we have to.
     fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket);
-    /// Determines if the state should transition to another state based on the context.
-    fn should_transition(&self, context: &Context) -> Option<RocketState>;
 }
 
 pub enum RocketState {
@@ -17,39 +26,238 @@ pub enum RocketState {
     Countdown(CountdownState),
     MotorBurn(MotorBurnState),
     CoastState(CoastState),
-    FreeFall(FreeFallState),
+    DrogueDescent(DrogueDescentState),
+    MainDescent(MainDescentState),
     Landed(LandedState),
+    Recovery(RecoveryState),
+    /// Stopped by an uplink command or a safety check on the pad.
+    Abort(AbortState),
+    /// Stopped because the flight computer itself failed, e.g. the IMU stopped reading.
+    Fault(FaultState),
     Shutdown,
 }
 
+/// Which state the rocket is in, without the state's data. Used by the transition table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StateId {
+    Standby,
+    Countdown,
+    MotorBurn,
+    Coast,
+    DrogueDescent,
+    MainDescent,
+    Landed,
+    Recovery,
+    Abort,
+    Fault,
+    Shutdown,
+}
+
+impl StateId {
+    pub const ALL: [StateId; 11] = [
+        StateId::Standby,
+        StateId::Countdown,
+        StateId::MotorBurn,
+        StateId::Coast,
+        StateId::DrogueDescent,
+        StateId::MainDescent,
+        StateId::Landed,
+        StateId::Recovery,
+        StateId::Abort,
+        StateId::Fault,
+        StateId::Shutdown,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateId::Standby => "Standby",
+            StateId::Countdown => "Countdown",
+            StateId::MotorBurn => "MotorBurn",
+            StateId::Coast => "Coast",
+            StateId::DrogueDescent => "DrogueDescent",
+            StateId::MainDescent => "MainDescent",
+            StateId::Landed => "Landed",
+            StateId::Recovery => "Recovery",
+            StateId::Abort => "Abort",
+            StateId::Fault => "Fault",
+            StateId::Shutdown => "Shutdown",
+        }
+    }
+}
+
+/// The single character a state is written as in the log and sent as in telemetry, from its name.
+/// Every state gets its own letter, so they can't be confused the way first letters can (Coast
+/// and Countdown, MotorBurn and MainDescent).
+pub fn state_letter(name: &str) -> char {
+    match name {
+        "Standby" => 'S',
+        "Countdown" => 'C',
+        "MotorBurn" => 'M',
+        "Coast" => 'O',
+        "DrogueDescent" => 'D',
+        "MainDescent" => 'N',
+        "Landed" => 'L',
+        "Recovery" => 'R',
+        "Abort" => 'A',
+        // 'F' was FreeFall in older logs:
+        "Fault" => 'Z',
+        "Shutdown" => 'X',
+        _ => 'U', // Unknown
+    }
+}
+
 pub struct StandbyState {}
 
-pub struct CountdownState {}
+/// On the pad, waiting for liftoff.
+pub struct CountdownState {
+    /// Consecutive samples with the acceleration above the launch threshold:
+    high_acceleration_samples: u32,
+    /// Start of the current run of high acceleration, which is liftoff if the launch is confirmed:
+    acceleration_start: Option<std::time::Instant>,
+    /// Set once the acceleration has been high for long enough, until the barometer confirms it:
+    acceleration_trigger: Option<std::time::Instant>,
+    /// Altitude before the acceleration went up:
+    pad_altitude: f32,
+}
 
-pub struct MotorBurnState {}
+pub struct MotorBurnState {
+    /// When the acceleration first went above the launch threshold:
+    pub liftoff_time: std::time::Instant,
+    peak_velocity: f32,
+    /// Consecutive samples with the acceleration below the burnout threshold:
+    low_acceleration_samples: u32,
+    /// Start of the current run of low acceleration, which is burnout if it lasts:
+    low_acceleration_start: Option<std::time::Instant>,
+}
 
-pub struct CoastState {}
+pub struct CoastState {
+    /// When the motor burnt out:
+    pub burnout_time: std::time::Instant,
+    /// Velocity at burnout, for the apogee backup timer:
+    burnout_velocity: f32,
+    /// Consecutive samples with the velocity at or below zero:
+    descending_velocity_samples: u32,
+}
 
-pub struct FreeFallState {
-    start_time: std::time::Instant,
+/// Coming down under the drogue parachute, from apogee to the main deployment altitude.
+pub struct DrogueDescentState {
+    landing: LandingDetector,
+    descent_rate: DescentRate,
+}
+
+/// Coming down under the main parachute.
+pub struct MainDescentState {
+    landing: LandingDetector,
+    descent_rate: DescentRate,
+}
+
+/// Decides when we've landed, over both descent phases.
+#[derive(Clone)]
+struct LandingDetector {
+    /// Apogee, for the descent timeout:
+    descent_start: std::time::Instant,
+    /// The last `LANDED_WINDOW_SAMPLES` (altitude, vertical velocity, acceleration magnitude):
+    recent_samples: VecDeque<(f32, f32, f32)>,
+    /// Start of the current run of slow samples below `GROUND_ALTITUDE_METERS`, for the fallback
+    /// rule:
     landing_timer: Option<std::time::Instant>,
-    started_landed_timer: bool,
+}
+
+/// Descent rate estimate for one descent phase, used to detect a failed parachute.
+struct DescentRate {
+    phase_start: std::time::Instant,
+    /// Descent rates (positive down) of the last `DESCENT_RATE_SAMPLES`:
+    recent: VecDeque<f32>,
+    total: f32,
+    samples: u32,
+    failure_reported: bool,
 }
 
 pub struct LandedState {
     start_time: std::time::Instant,
 }
 
+/// Transmitting the recovery beacon after landing.
+pub struct RecoveryState {
+    start_time: std::time::Instant,
+}
+
+/// Pyros disarmed, transmitting the beacon until shutdown.
+pub struct AbortState {
+    start_time: std::time::Instant,
+}
+
+/// Pyros disarmed after a sensor failure on the pad, transmitting the beacon until shutdown.
+pub struct FaultState {
+    start_time: std::time::Instant,
+}
+
 impl RocketState {
-    pub fn name(&self) -> &'static str {
+    pub fn id(&self) -> StateId {
         match self {
-            RocketState::Standby(_) => "Standby",
-            RocketState::Countdown(_) => "Countdown",
-            RocketState::MotorBurn(_) => "MotorBurn",
-            RocketState::CoastState(_) => "Coast",
-            RocketState::FreeFall(_) => "FreeFall",
-            RocketState::Landed(_) => "Landed",
-            RocketState::Shutdown => "Shutdown",
+            RocketState::Standby(_) => StateId::Standby,
+            RocketState::Countdown(_) => StateId::Countdown,
+            RocketState::MotorBurn(_) => StateId::MotorBurn,
+            RocketState::CoastState(_) => StateId::Coast,
+            RocketState::DrogueDescent(_) => StateId::DrogueDescent,
+            RocketState::MainDescent(_) => StateId::MainDescent,
+            RocketState::Landed(_) => StateId::Landed,
+            RocketState::Recovery(_) => StateId::Recovery,
+            RocketState::Abort(_) => StateId::Abort,
+            RocketState::Fault(_) => StateId::Fault,
+            RocketState::Shutdown => StateId::Shutdown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.id().name()
+    }
+
+    /// The state to switch to when a transition to `to` fires in this one, carrying over what
+    /// the new state needs from this one (e.g. the liftoff time).
+    pub fn enter(&self, to: StateId, inputs: &GuardInputs) -> RocketState {
+        let now = inputs.now;
+        match to {
+            StateId::Standby => RocketState::Standby(StandbyState {}),
+            StateId::Countdown => RocketState::Countdown(CountdownState::new()),
+            StateId::MotorBurn => {
+                let liftoff_time = match self {
+                    RocketState::Countdown(state) => state.liftoff_time(now),
+                    _ => now,
+                };
+                RocketState::MotorBurn(MotorBurnState::new(liftoff_time))
+            }
+            StateId::Coast => {
+                let velocity = inputs.processor.vertical_velocity;
+                let (burnout_time, burnout_velocity) = match self {
+                    RocketState::MotorBurn(state) => {
+                        (state.burnout_time(now), velocity.max(state.peak_velocity))
+                    }
+                    _ => (now, velocity),
+                };
+                RocketState::CoastState(CoastState {
+                    burnout_time,
+                    burnout_velocity,
+                    descending_velocity_samples: 0,
+                })
+            }
+            StateId::DrogueDescent => RocketState::DrogueDescent(DrogueDescentState::new()),
+            StateId::MainDescent => {
+                // Landing detection carries on from the drogue descent:
+                let landing = match self {
+                    RocketState::DrogueDescent(state) => state.landing.clone(),
+                    _ => LandingDetector::new(),
+                };
+                RocketState::MainDescent(MainDescentState {
+                    landing,
+                    descent_rate: DescentRate::new(),
+                })
+            }
+            StateId::Landed => RocketState::Landed(LandedState { start_time: now }),
+            StateId::Recovery => RocketState::Recovery(RecoveryState { start_time: now }),
+            StateId::Abort => RocketState::Abort(AbortState { start_time: now }),
+            StateId::Fault => RocketState::Fault(FaultState { start_time: now }),
+            StateId::Shutdown => RocketState::Shutdown,
         }
     }
 
@@ -59,103 +267,587 @@ impl RocketState {
             RocketState::Countdown(state) => state.update_internal(processor_data_packet),
             RocketState::MotorBurn(state) => state.update_internal(processor_data_packet),
             RocketState::CoastState(state) => state.update_internal(processor_data_packet),
-            RocketState::FreeFall(state) => state.update_internal(processor_data_packet),
+            RocketState::DrogueDescent(state) => state.update_internal(processor_data_packet),
+            RocketState::MainDescent(state) => state.update_internal(processor_data_packet),
             RocketState::Landed(state) => state.update_internal(processor_data_packet),
+            RocketState::Recovery(state) => state.update_internal(processor_data_packet),
+            RocketState::Abort(state) => state.update_internal(processor_data_packet),
+            RocketState::Fault(state) => state.update_internal(processor_data_packet),
             RocketState::Shutdown => {}
         }
     }
 
-    pub fn should_transition(&self, context: &Context) -> Option<RocketState> {
+    /// Whether only the low-rate beacon is transmitted, instead of the full telemetry.
+    pub fn is_beacon_mode(&self) -> bool {
+        matches!(
+            self,
+            RocketState::Recovery(_) | RocketState::Abort(_) | RocketState::Fault(_)
+        )
+    }
+
+    /// Whether we're still on the pad, where an abort or fault can stop the flight.
+    pub fn is_before_launch(&self) -> bool {
+        matches!(self, RocketState::Standby(_) | RocketState::Countdown(_))
+    }
+
+    /// Whether the flight is over one way or another.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            RocketState::Landed(_)
+                | RocketState::Recovery(_)
+                | RocketState::Abort(_)
+                | RocketState::Fault(_)
+                | RocketState::Shutdown
+        )
+    }
+
+    /// Checks whether the parachute for the current descent phase has failed. Returns why, the
+    /// first time it looks like it has.
+    pub fn check_chute_failure(&mut self, config: &DeploymentConfig) -> Option<String> {
+        match self {
+            RocketState::DrogueDescent(state) => state
+                .descent_rate
+                .check_failure("Drogue", config.max_drogue_descent_rate),
+            RocketState::MainDescent(state) => state
+                .descent_rate
+                .check_failure("Main", config.max_main_descent_rate),
+            _ => None,
+        }
+    }
+
+    /// Average descent rate so far in the current descent phase, if we're in one.
+    pub fn average_descent_rate(&self) -> Option<f32> {
         match self {
-            RocketState::Standby(state) => state.should_transition(context),
-            RocketState::Countdown(state) => state.should_transition(context),
-            RocketState::MotorBurn(state) => state.should_transition(context),
-            RocketState::CoastState(state) => state.should_transition(context),
-            RocketState::FreeFall(state) => state.should_transition(context),
-            RocketState::Landed(state) => state.should_transition(context),
-            RocketState::Shutdown => None,
+            RocketState::DrogueDescent(state) => state.descent_rate.average(),
+            RocketState::MainDescent(state) => state.descent_rate.average(),
+            _ => None,
         }
     }
 }
 
 impl State for StandbyState {
     fn update_internal(&mut self, _: &ProcessorDataPacket) {}
-    fn should_transition(&self, _: &Context) -> Option<RocketState> {
-        // Unfortunately, the logic for this is in context. I'm not good enough at Rust yet.
-        None
+}
+
+impl CountdownState {
+    pub fn new() -> Self {
+        CountdownState {
+            high_acceleration_samples: 0,
+            acceleration_start: None,
+            acceleration_trigger: None,
+            pad_altitude: 0.0,
+        }
+    }
+
+    /// Why we think the rocket has lifted off, if we do: sustained acceleration confirmed by the
+    /// velocity or altitude, or the altitude alone in case the accelerometer failed.
+    pub fn launch_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        let altitude = inputs.processor.current_altitude;
+        let velocity = inputs.processor.vertical_velocity;
+
+        if let Some(liftoff_time) = self.acceleration_trigger {
+            let altitude_gain = altitude - self.pad_altitude;
+            let confirmation = if velocity >= TAKEOFF_VELOCITY_METERS_PER_SECOND {
+                Some(format!("velocity {:.1} m/s", velocity))
+            } else if altitude_gain >= LAUNCH_ALTITUDE_GAIN_METERS {
+                Some(format!("altitude gain {:.1} m", altitude_gain))
+            } else {
+                None
+            };
+            if let Some(confirmation) = confirmation {
+                return Some(format!(
+                    "acceleration above {:.1} m/s^2 for {} samples, confirmed by {}; liftoff {:.3} s ago",
+                    LAUNCH_ACCELERATION_METERS_PER_SECOND_SQUARED,
+                    LAUNCH_ACCELERATION_SAMPLES,
+                    confirmation,
+                    inputs.now.duration_since(liftoff_time).as_secs_f32()
+                ));
+            }
+        }
+
+        // In case the accelerometer failed:
+        (altitude >= GROUND_ALTITUDE_METERS).then(|| {
+            format!(
+                "altitude {:.1} m reached {:.1} m without an acceleration trigger",
+                altitude, GROUND_ALTITUDE_METERS
+            )
+        })
+    }
+
+    /// Start of the acceleration that triggered the launch, or `now` if only the barometer saw it.
+    fn liftoff_time(&self, now: std::time::Instant) -> std::time::Instant {
+        self.acceleration_trigger.unwrap_or(now)
+    }
+}
+
+impl Default for CountdownState {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl State for CountdownState {
-    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
-    fn should_transition(&self, context: &Context) -> Option<RocketState> {
-        if context.data_processor.current_altitude >= GROUND_ALTITUDE_METERS {
-            Some(RocketState::MotorBurn(MotorBurnState {}))
+    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        let acceleration = processor_data_packet
+            .acceleration
+            .iter()
+            .map(|a| a * a)
+            .sum::<f32>()
+            .sqrt();
+
+        if acceleration >= LAUNCH_ACCELERATION_METERS_PER_SECOND_SQUARED {
+            if self.high_acceleration_samples == 0 {
+                self.acceleration_start = Some(std::time::Instant::now());
+            }
+            self.high_acceleration_samples += 1;
+            if self.high_acceleration_samples == LAUNCH_ACCELERATION_SAMPLES {
+                self.acceleration_trigger = self.acceleration_start;
+            }
+        } else {
+            self.high_acceleration_samples = 0;
+            if self.acceleration_trigger.is_none() {
+                self.pad_altitude = processor_data_packet.current_altitude;
+            }
+        }
+
+        // A bump on the pad that the barometer never confirms:
+        if let Some(trigger) = self.acceleration_trigger
+            && trigger.elapsed().as_secs_f32() > LAUNCH_CONFIRMATION_SECONDS
+        {
+            self.acceleration_trigger = None;
+            self.pad_altitude = processor_data_packet.current_altitude;
+        }
+    }
+}
+
+impl MotorBurnState {
+    pub fn new(liftoff_time: std::time::Instant) -> Self {
+        MotorBurnState {
+            liftoff_time,
+            peak_velocity: 0.0,
+            low_acceleration_samples: 0,
+            low_acceleration_start: None,
+        }
+    }
+
+    /// Why we think the motor has burnt out, if we do: the acceleration dropping below gravity,
+    /// or the velocity falling off its peak.
+    pub fn burnout_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        let velocity = inputs.processor.vertical_velocity;
+        let criterion = if self.low_acceleration_samples >= BURNOUT_SAMPLES {
+            format!(
+                "acceleration below {:.1} m/s^2 for {} samples",
+                BURNOUT_ACCELERATION_METERS_PER_SECOND_SQUARED, BURNOUT_SAMPLES
+            )
+        } else if self.peak_velocity >= TAKEOFF_VELOCITY_METERS_PER_SECOND
+            && velocity < self.peak_velocity * MAX_VELOCITY_THRESHOLD
+        {
+            format!(
+                "velocity {:.1} m/s below {} of peak {:.1} m/s",
+                velocity, MAX_VELOCITY_THRESHOLD, self.peak_velocity
+            )
         } else {
-            None
+            return None;
+        };
+
+        let burn_time = self
+            .burnout_time(inputs.now)
+            .duration_since(self.liftoff_time);
+        Some(format!(
+            "{}; burn time {:.3} s",
+            criterion,
+            burn_time.as_secs_f32()
+        ))
+    }
+
+    /// Start of the low acceleration if that's how burnout was detected, otherwise `now`.
+    fn burnout_time(&self, now: std::time::Instant) -> std::time::Instant {
+        match self.low_acceleration_start {
+            Some(start) if self.low_acceleration_samples >= BURNOUT_SAMPLES => start,
+            _ => now,
         }
     }
 }
 
 impl State for MotorBurnState {
-    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
-    fn should_transition(&self, context: &Context) -> Option<RocketState> {
-        if context.data_processor.current_altitude >= context.data_processor.max_altitude * MAX_ALTITUDE_THRESHOLD {
-            Some(RocketState::CoastState(CoastState {}))
+    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        self.peak_velocity = self
+            .peak_velocity
+            .max(processor_data_packet.vertical_velocity);
+
+        let acceleration = processor_data_packet
+            .acceleration
+            .iter()
+            .map(|a| a * a)
+            .sum::<f32>()
+            .sqrt();
+        if acceleration < BURNOUT_ACCELERATION_METERS_PER_SECOND_SQUARED {
+            if self.low_acceleration_samples == 0 {
+                self.low_acceleration_start = Some(std::time::Instant::now());
+            }
+            self.low_acceleration_samples += 1;
         } else {
-            None
+            self.low_acceleration_samples = 0;
+            self.low_acceleration_start = None;
         }
     }
 }
 
+impl CoastState {
+    /// Time after burnout by which we must be past apogee: the coast time without drag, plus a
+    /// margin.
+    fn apogee_timer_seconds(&self) -> f32 {
+        self.burnout_velocity.max(0.0) / GRAVITY_METERS_PER_SECOND_SQUARED
+            + APOGEE_TIMER_MARGIN_SECONDS
+    }
+
+    /// Why we think we're past apogee, if enough of the criteria agree. Never while the Mach
+    /// lockout is on, whatever the pressure transients say.
+    pub fn apogee_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        if inputs.processor.mach_lockout {
+            return None;
+        }
+        let altitude = inputs.processor.current_altitude;
+        let max_altitude = inputs.processor.maximum_altitude;
+        let since_burnout = inputs.now.duration_since(self.burnout_time).as_secs_f32();
+
+        // Each criterion that thinks we're past apogee gets a vote:
+        let mut votes = Vec::new();
+        if self.descending_velocity_samples >= APOGEE_VELOCITY_SAMPLES {
+            votes.push(format!(
+                "velocity at or below zero for {} samples",
+                self.descending_velocity_samples
+            ));
+        }
+        if altitude <= max_altitude - APOGEE_ALTITUDE_DROP_METERS {
+            votes.push(format!(
+                "altitude {:.1} m, {:.1} m below max altitude {:.1} m",
+                altitude,
+                max_altitude - altitude,
+                max_altitude
+            ));
+        }
+        if since_burnout >= self.apogee_timer_seconds() {
+            votes.push(format!(
+                "backup timer of {:.1} s since burnout expired",
+                self.apogee_timer_seconds()
+            ));
+        }
+
+        (votes.len() >= APOGEE_VOTES_REQUIRED).then(|| format!("apogee by {}", votes.join(" and ")))
+    }
+
+    /// Backstop in case the apogee criteria never agree.
+    pub fn coast_timeout_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        let since_burnout = inputs.now.duration_since(self.burnout_time).as_secs_f32();
+        (since_burnout >= MAX_COAST_SECONDS)
+            .then(|| format!("coast timed out after {} s", MAX_COAST_SECONDS))
+    }
+}
+
 impl State for CoastState {
-    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
-    fn should_transition(&self, context: &Context) -> Option<RocketState> {
-        if context.data_processor.current_altitude < context.data_processor.max_altitude * MAX_ALTITUDE_THRESHOLD {
-            Some(RocketState::FreeFall(FreeFallState {
-                start_time: std::time::Instant::now(),
-                landing_timer: None,
-                started_landed_timer: false,
-            }))
+    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        if processor_data_packet.vertical_velocity <= 0.0 {
+            self.descending_velocity_samples += 1;
         } else {
-            None
+            self.descending_velocity_samples = 0;
         }
     }
 }
 
-impl State for FreeFallState {
-    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+impl LandingDetector {
+    fn new() -> Self {
+        LandingDetector {
+            descent_start: std::time::Instant::now(),
+            recent_samples: VecDeque::with_capacity(LANDED_WINDOW_SAMPLES + 1),
+            landing_timer: None,
+        }
+    }
+
+    fn update(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        let acceleration = processor_data_packet
+            .acceleration
+            .iter()
+            .map(|a| a * a)
+            .sum::<f32>()
+            .sqrt();
+        self.recent_samples.push_back((
+            processor_data_packet.current_altitude,
+            processor_data_packet.vertical_velocity,
+            acceleration,
+        ));
+        if self.recent_samples.len() > LANDED_WINDOW_SAMPLES {
+            self.recent_samples.pop_front();
+        }
+
+        // Fallback for when the acceleration or altitude is too noisy to ever look stationary.
+        // Anything moving faster than a landed rocket starts it over:
         if processor_data_packet.current_altitude <= GROUND_ALTITUDE_METERS
-            && !self.started_landed_timer
+            && processor_data_packet.vertical_velocity.abs() < LANDED_VELOCITY_METERS_PER_SECOND
         {
-            self.landing_timer = Some(std::time::Instant::now());
-            self.started_landed_timer = true;
+            self.landing_timer
+                .get_or_insert_with(std::time::Instant::now);
+        } else {
+            self.landing_timer = None;
         }
     }
 
-    fn should_transition(&self, _: &Context) -> Option<RocketState> {
-        if let Some(timer) = self.landing_timer {
-            if timer.elapsed().as_secs() >= SECONDS_TO_CONSIDERED_LANDED {
-                return Some(RocketState::Landed(LandedState {
-                    start_time: std::time::Instant::now(),
-                }));
-            }
-        } else if self.start_time.elapsed().as_secs() >= MAX_FREE_FALL_SECONDS {
-            return Some(RocketState::Landed(LandedState {
-                start_time: std::time::Instant::now(),
-            }));
+    /// Why we think the rocket has landed, if we do.
+    fn landed_reason(&self, now: std::time::Instant) -> Option<String> {
+        if let Some(reason) = self.stationary_reason() {
+            return Some(reason);
+        }
+        if let Some(timer) = self.landing_timer
+            && now.duration_since(timer).as_secs() >= SECONDS_TO_CONSIDERED_LANDED
+        {
+            return Some(format!(
+                "below {:.1} m and slower than {:.1} m/s for {} s, too noisy to look stationary",
+                GROUND_ALTITUDE_METERS,
+                LANDED_VELOCITY_METERS_PER_SECOND,
+                SECONDS_TO_CONSIDERED_LANDED
+            ));
+        }
+        (now.duration_since(self.descent_start).as_secs() >= MAX_DESCENT_SECONDS)
+            .then(|| format!("descent timed out after {} s", MAX_DESCENT_SECONDS))
+    }
+
+    /// Why we think the rocket is lying still: near-zero velocity, steady acceleration and steady
+    /// altitude over the whole window. None if any of them isn't.
+    fn stationary_reason(&self) -> Option<String> {
+        if self.recent_samples.len() < LANDED_WINDOW_SAMPLES {
+            return None;
+        }
+        let max_speed = self
+            .recent_samples
+            .iter()
+            .map(|(_, velocity, _)| velocity.abs())
+            .fold(0.0, f32::max);
+        if max_speed >= LANDED_VELOCITY_METERS_PER_SECOND {
+            return None;
         }
-        None
+
+        let count = self.recent_samples.len() as f32;
+        let mean_acceleration = self
+            .recent_samples
+            .iter()
+            .map(|(_, _, acceleration)| acceleration)
+            .sum::<f32>()
+            / count;
+        let acceleration_stddev = (self
+            .recent_samples
+            .iter()
+            .map(|(_, _, acceleration)| (acceleration - mean_acceleration).powi(2))
+            .sum::<f32>()
+            / count)
+            .sqrt();
+        if acceleration_stddev >= LANDED_ACCELERATION_STDDEV_METERS_PER_SECOND_SQUARED {
+            return None;
+        }
+
+        let (min_altitude, max_altitude) = self
+            .recent_samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(low, high), (altitude, _, _)| {
+                (low.min(*altitude), high.max(*altitude))
+            });
+        if max_altitude - min_altitude >= LANDED_ALTITUDE_RANGE_METERS {
+            return None;
+        }
+
+        Some(format!(
+            "stationary for {} samples: speed below {:.2} m/s, acceleration stddev {:.2} m/s^2, \
+             altitude within {:.2} m of {:.1} m",
+            LANDED_WINDOW_SAMPLES,
+            max_speed,
+            acceleration_stddev,
+            max_altitude - min_altitude,
+            min_altitude
+        ))
+    }
+}
+
+impl DescentRate {
+    fn new() -> Self {
+        DescentRate {
+            phase_start: std::time::Instant::now(),
+            recent: VecDeque::with_capacity(DESCENT_RATE_SAMPLES + 1),
+            total: 0.0,
+            samples: 0,
+            failure_reported: false,
+        }
+    }
+
+    fn update(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        let rate = -processor_data_packet.vertical_velocity;
+        self.recent.push_back(rate);
+        if self.recent.len() > DESCENT_RATE_SAMPLES {
+            self.recent.pop_front();
+        }
+        self.total += rate;
+        self.samples += 1;
+    }
+
+    /// Descent rate over the last `DESCENT_RATE_SAMPLES`, once we have that many.
+    fn current(&self) -> Option<f32> {
+        if self.recent.len() < DESCENT_RATE_SAMPLES {
+            return None;
+        }
+        Some(self.recent.iter().sum::<f32>() / self.recent.len() as f32)
+    }
+
+    /// Average descent rate over the whole phase.
+    fn average(&self) -> Option<f32> {
+        (self.samples > 0).then(|| self.total / self.samples as f32)
+    }
+
+    /// Reports the chute as failed (once) if, after it's had time to open, we're still coming
+    /// down faster than `max_rate`.
+    fn check_failure(&mut self, chute: &str, max_rate: f32) -> Option<String> {
+        if self.failure_reported
+            || self.phase_start.elapsed().as_secs_f32() < CHUTE_DEPLOY_GRACE_SECONDS
+        {
+            return None;
+        }
+        let rate = self.current()?;
+        if rate <= max_rate {
+            return None;
+        }
+        self.failure_reported = true;
+        Some(format!(
+            "{} descent rate {:.1} m/s is above the limit of {:.1} m/s",
+            chute, rate, max_rate
+        ))
+    }
+}
+
+impl DrogueDescentState {
+    pub fn new() -> Self {
+        DrogueDescentState {
+            landing: LandingDetector::new(),
+            descent_rate: DescentRate::new(),
+        }
+    }
+
+    /// Why we think the rocket has landed, if we do. It can come down on the drogue alone if the
+    /// main never deploys.
+    pub fn landed_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        self.landing.landed_reason(inputs.now)
+    }
+
+    /// Why the main should be out, if we're down to its deployment altitude.
+    pub fn main_deploy_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        let altitude = inputs.processor.current_altitude;
+        let main_deploy_altitude = inputs.config.deployment.main_deploy_altitude;
+        (altitude <= main_deploy_altitude).then(|| {
+            format!(
+                "altitude {:.1} m at or below the main deployment altitude of {:.1} m",
+                altitude, main_deploy_altitude
+            )
+        })
+    }
+}
+
+impl Default for DrogueDescentState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State for DrogueDescentState {
+    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        self.landing.update(processor_data_packet);
+        self.descent_rate.update(processor_data_packet);
+    }
+}
+
+impl MainDescentState {
+    /// Why we think the rocket has landed, if we do.
+    pub fn landed_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        self.landing.landed_reason(inputs.now)
+    }
+}
+
+impl State for MainDescentState {
+    fn update_internal(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        self.landing.update(processor_data_packet);
+        self.descent_rate.update(processor_data_packet);
+    }
+}
+
+impl LandedState {
+    /// Why we're done waiting on the ground, once we've been there for 5 seconds.
+    pub fn settled_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        (inputs.now.duration_since(self.start_time).as_secs() >= 5)
+            .then(|| String::from("landed for 5 s"))
     }
 }
 
 impl State for LandedState {
     fn update_internal(&mut self, _: &ProcessorDataPacket) {}
-    fn should_transition(&self, _: &Context) -> Option<RocketState> {
-        // Switch to shutdown state after 5 seconds:
-        if self.start_time.elapsed().as_secs() >= 5 {
-            return Some(RocketState::Shutdown {});
+}
+
+impl RecoveryState {
+    /// Why the beacon is done and we can shut down, if it is.
+    pub fn beacon_done_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        beacon_done_reason(self.start_time, inputs)
+    }
+}
+
+impl State for RecoveryState {
+    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
+}
+
+impl AbortState {
+    /// Why the beacon is done and we can shut down, if it is.
+    pub fn beacon_done_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        beacon_done_reason(self.start_time, inputs)
+    }
+}
+
+impl State for AbortState {
+    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
+}
+
+impl FaultState {
+    /// Why the beacon is done and we can shut down, if it is.
+    pub fn beacon_done_reason(&self, inputs: &GuardInputs) -> Option<String> {
+        beacon_done_reason(self.start_time, inputs)
+    }
+}
+
+impl State for FaultState {
+    fn update_internal(&mut self, _: &ProcessorDataPacket) {}
+}
+
+/// Shuts down once the beacon has run for its duration since `start_time`, or right away if it's
+/// disabled.
+fn beacon_done_reason(start_time: std::time::Instant, inputs: &GuardInputs) -> Option<String> {
+    if !inputs.config.recovery.beacon_enabled {
+        return Some(String::from("recovery beacon disabled"));
+    }
+    let duration = inputs.config.recovery.beacon_duration_seconds;
+    (inputs.now.duration_since(start_time).as_secs() >= duration)
+        .then(|| format!("beacon ran for {} s", duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn every_state_has_its_own_letter() {
+        let letters: BTreeSet<char> = StateId::ALL
+            .iter()
+            .map(|state| state_letter(state.name()))
+            .collect();
+        assert_eq!(letters.len(), StateId::ALL.len());
+        // Not the unknown state, FreeFall in older logs, or the other telemetry lines:
+        for taken in ['U', 'F', 'G', 'B', 'E', 'T'] {
+            assert!(!letters.contains(&taken), "{} is taken", taken);
         }
-        None
+        assert_eq!(state_letter("Coast"), 'O');
+        assert_eq!(state_letter("Fault"), 'Z');
     }
 }
@@ -0,0 +1,107 @@
+//! Summary of the flight so far, used in the recovery beacon and saved on shutdown.
+
+use crate::data_processor::ProcessorDataPacket;
+use crate::state::RocketState;
+use crate::transmitter::LinkStats;
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct FlightSummary {
+    pub launch_time: Option<Instant>,
+    pub burnout_time: Option<Instant>,
+    pub landing_time: Option<Instant>,
+    pub max_altitude: f32,
+    pub max_velocity: f32,
+    /// Average descent rates under each parachute, set as each descent phase ends:
+    pub drogue_descent_rate: Option<f32>,
+    pub main_descent_rate: Option<f32>,
+    /// Set at shutdown, if we had a transmitter:
+    pub link_stats: Option<LinkStats>,
+}
+
+impl FlightSummary {
+    pub fn update(&mut self, processor_data_packet: &ProcessorDataPacket) {
+        self.max_altitude = self
+            .max_altitude
+            .max(processor_data_packet.maximum_altitude);
+        self.max_velocity = self
+            .max_velocity
+            .max(processor_data_packet.maximum_velocity);
+    }
+
+    /// Records the launch and landing times as the rocket enters the corresponding states, and the
+    /// descent rates as it leaves them.
+    pub fn record_transition(&mut self, old_state: &RocketState, new_state: &RocketState) {
+        match old_state {
+            RocketState::DrogueDescent(_) => {
+                self.drogue_descent_rate = old_state.average_descent_rate()
+            }
+            RocketState::MainDescent(_) => {
+                self.main_descent_rate = old_state.average_descent_rate()
+            }
+            _ => {}
+        }
+        match new_state {
+            RocketState::MotorBurn(state) => self.launch_time = Some(state.liftoff_time),
+            RocketState::CoastState(state) => self.burnout_time = Some(state.burnout_time),
+            RocketState::Landed(_) => self.landing_time = Some(Instant::now()),
+            _ => {}
+        }
+    }
+
+    /// Time from liftoff to burnout.
+    pub fn burn_time(&self) -> Option<Duration> {
+        Some(self.burnout_time?.duration_since(self.launch_time?))
+    }
+
+    /// Time from launch to landing, or to now if we haven't landed yet.
+    pub fn flight_time(&self) -> Option<Duration> {
+        let launch_time = self.launch_time?;
+        Some(match self.landing_time {
+            Some(landing_time) => landing_time.duration_since(launch_time),
+            None => launch_time.elapsed(),
+        })
+    }
+
+    /// Writes the summary as `key: value` lines, and waits until it's on the SD card.
+    pub fn write_to_file(&self, path: &str) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "max_altitude_m: {:.2}", self.max_altitude)?;
+        writeln!(file, "max_velocity_m_per_s: {:.2}", self.max_velocity)?;
+        writeln!(file, "launch_detected: {}", self.launch_time.is_some())?;
+        writeln!(file, "landing_detected: {}", self.landing_time.is_some())?;
+        match self.flight_time() {
+            Some(flight_time) => writeln!(file, "flight_time_s: {:.2}", flight_time.as_secs_f32())?,
+            None => writeln!(file, "flight_time_s:")?,
+        }
+        match self.burn_time() {
+            Some(burn_time) => writeln!(file, "burn_time_s: {:.3}", burn_time.as_secs_f32())?,
+            None => writeln!(file, "burn_time_s:")?,
+        }
+        match self.drogue_descent_rate {
+            Some(rate) => writeln!(file, "drogue_descent_rate_m_per_s: {:.2}", rate)?,
+            None => writeln!(file, "drogue_descent_rate_m_per_s:")?,
+        }
+        match self.main_descent_rate {
+            Some(rate) => writeln!(file, "main_descent_rate_m_per_s: {:.2}", rate)?,
+            None => writeln!(file, "main_descent_rate_m_per_s:")?,
+        }
+        if let Some(link_stats) = &self.link_stats {
+            writeln!(file, "telemetry_packets_sent: {}", link_stats.packets_sent)?;
+            writeln!(file, "telemetry_bytes_sent: {}", link_stats.bytes_sent)?;
+            writeln!(
+                file,
+                "telemetry_send_failures: {}",
+                link_stats.send_failures
+            )?;
+            writeln!(
+                file,
+                "telemetry_commands_received: {}",
+                link_stats.commands_received
+            )?;
+        }
+        file.sync_all()
+    }
+}
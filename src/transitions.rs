@@ -0,0 +1,960 @@
+//! The state machine's transitions as a table: which state each one leaves, the guard that has to
+//! hold, the state it enters and what happens on the way.
+//!
+//! Guards only see the current state and a `GuardInputs`, not the whole `Context`, so each one can
+//! be checked on its own. Rules are tried in order and the first guard that holds wins, so the
+//! abort and fault rules come first. Run `cargo run --bin salt-states` to print the table as a
+//! diagram, with the debounce from the flight config, and check that every state can be reached.
+//!
+//! A guard has to hold for its debounce (a number of consecutive cycles and/or a duration) before
+//! its transition fires. Each rule has a default, which `[debounce.<guard>]` in the config can
+//! override, and `Debouncer` keeps the counters.
+
+use crate::config::{DebounceConfig, FlightConfig, PyroTrigger};
+use crate::data_processor::ProcessorDataPacket;
+use crate::safety::SafetyTrip;
+use crate::state::{RocketState, StateId};
+use crate::warn;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// Everything the guards can look at, besides the state itself.
+pub struct GuardInputs<'a> {
+    pub now: Instant,
+    pub processor: &'a ProcessorDataPacket,
+    pub config: &'a FlightConfig,
+    /// Command received over the uplink this cycle:
+    pub command: Option<&'a str>,
+    /// What the safety checks decided this cycle:
+    pub safety_trip: Option<&'a SafetyTrip>,
+}
+
+/// Something to do when a transition fires, besides switching state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransitionAction {
+    None,
+    /// Record whether the arming switch is on and the igniters have continuity.
+    ReportPyroStatus,
+    FirePyros(PyroTrigger),
+    DisarmPyros,
+}
+
+/// One row of the transition table.
+pub struct TransitionRule {
+    pub from: &'static [StateId],
+    /// Name of the guard in the config and telemetry:
+    pub key: &'static str,
+    /// Short description of the guard, for the diagram:
+    pub guard_name: &'static str,
+    /// Returns why the transition should fire, if it should.
+    pub guard: fn(&RocketState, &GuardInputs) -> Option<String>,
+    pub to: StateId,
+    pub action: TransitionAction,
+    /// How long the guard has to hold, unless the config says otherwise:
+    pub debounce: DebounceConfig,
+}
+
+/// A state change, and why it happened (for the event log).
+pub struct Transition {
+    pub state: RocketState,
+    pub reason: String,
+    pub action: TransitionAction,
+}
+
+/// States an abort or fault can happen in: the abort command and the safety checks only lead to
+/// Abort or Fault before launch, not from any state. After launch, stopping would only mean no
+/// parachutes, so the flight states carry on with whatever sensors are left, and a SALT ABORT
+/// received in flight is recorded and ignored (see `Context::update`).
+const BEFORE_LAUNCH: &[StateId] = &[StateId::Standby, StateId::Countdown];
+
+/// Guards on an uplink command, which is only seen for the one cycle it arrives in. Any debounce
+/// would mean the command never fires, so the config can't set one.
+const COMMAND_GUARDS: &[&str] = &["abort_command", "boot_command"];
+
+/// The transition table. Abort and Fault can only be entered before launch, see `BEFORE_LAUNCH`.
+pub const TRANSITIONS: &[TransitionRule] = &[
+    TransitionRule {
+        from: BEFORE_LAUNCH,
+        key: "abort_command",
+        guard_name: "SALT ABORT command",
+        guard: abort_command,
+        to: StateId::Abort,
+        action: TransitionAction::DisarmPyros,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: BEFORE_LAUNCH,
+        key: "safety_abort",
+        guard_name: "safety check failed",
+        guard: safety_abort,
+        to: StateId::Abort,
+        action: TransitionAction::DisarmPyros,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: BEFORE_LAUNCH,
+        key: "safety_fault",
+        guard_name: "flight computer failed",
+        guard: safety_fault,
+        to: StateId::Fault,
+        action: TransitionAction::DisarmPyros,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::Standby],
+        key: "boot_command",
+        guard_name: "SALT BOOT command",
+        guard: boot_command,
+        to: StateId::Countdown,
+        action: TransitionAction::ReportPyroStatus,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::Countdown],
+        key: "launch",
+        guard_name: "launch detected",
+        guard: launch,
+        to: StateId::MotorBurn,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::MotorBurn],
+        key: "burnout",
+        guard_name: "burnout",
+        guard: burnout,
+        to: StateId::Coast,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::samples(2),
+    },
+    TransitionRule {
+        from: &[StateId::Coast],
+        key: "apogee",
+        guard_name: "apogee",
+        guard: apogee,
+        to: StateId::DrogueDescent,
+        action: TransitionAction::FirePyros(PyroTrigger::Apogee),
+        debounce: DebounceConfig::samples(3),
+    },
+    TransitionRule {
+        from: &[StateId::Coast],
+        key: "coast_timeout",
+        guard_name: "coast timed out",
+        guard: coast_timeout,
+        to: StateId::DrogueDescent,
+        action: TransitionAction::FirePyros(PyroTrigger::Apogee),
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::DrogueDescent, StateId::MainDescent],
+        key: "landed",
+        guard_name: "landed",
+        guard: landed,
+        to: StateId::Landed,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::DrogueDescent],
+        key: "main_deploy",
+        guard_name: "main deployment altitude",
+        guard: main_deploy,
+        to: StateId::MainDescent,
+        action: TransitionAction::FirePyros(PyroTrigger::Main),
+        debounce: DebounceConfig::samples(3),
+    },
+    TransitionRule {
+        from: &[StateId::Landed],
+        key: "settled_with_beacon",
+        guard_name: "settled, beacon enabled",
+        guard: settled_with_beacon,
+        to: StateId::Recovery,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::Landed],
+        key: "settled_without_beacon",
+        guard_name: "settled, beacon disabled",
+        guard: settled_without_beacon,
+        to: StateId::Shutdown,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::NONE,
+    },
+    TransitionRule {
+        from: &[StateId::Recovery, StateId::Abort, StateId::Fault],
+        key: "beacon_done",
+        guard_name: "beacon done",
+        guard: beacon_done,
+        to: StateId::Shutdown,
+        action: TransitionAction::None,
+        debounce: DebounceConfig::NONE,
+    },
+];
+
+/// How long a guard has been holding, for telemetry.
+pub struct DebounceStatus {
+    pub key: &'static str,
+    pub samples: u32,
+    pub required_samples: u32,
+    pub held_ms: u64,
+    pub required_ms: u64,
+}
+
+/// When a guard started holding, while it holds:
+#[derive(Clone, Copy, Default)]
+struct GuardCounter {
+    samples: u32,
+    since: Option<Instant>,
+}
+
+/// Checks the guards every cycle and fires a transition once its guard has held for long enough.
+pub struct Debouncer {
+    /// Indexed like `TRANSITIONS`:
+    settings: Vec<DebounceConfig>,
+    counters: Vec<GuardCounter>,
+}
+
+impl Debouncer {
+    /// Uses the debounce from `overrides` for the guards it names, and the table's otherwise.
+    /// Overrides for unknown guards, and any debounce on a command guard, are ignored.
+    pub fn new(overrides: &BTreeMap<String, DebounceConfig>) -> Self {
+        for (key, debounce) in overrides {
+            if !TRANSITIONS.iter().any(|rule| rule.key == key) {
+                warn!("Unknown guard {} in the debounce config, ignoring it.", key);
+            } else if COMMAND_GUARDS.contains(&key.as_str()) && *debounce != DebounceConfig::NONE {
+                warn!(
+                    "Commands can't be debounced, ignoring the debounce config for {}.",
+                    key
+                );
+            }
+        }
+        Debouncer {
+            settings: TRANSITIONS
+                .iter()
+                .map(|rule| match overrides.get(rule.key) {
+                    Some(debounce) if !COMMAND_GUARDS.contains(&rule.key) => *debounce,
+                    _ => rule.debounce,
+                })
+                .collect(),
+            counters: vec![GuardCounter::default(); TRANSITIONS.len()],
+        }
+    }
+
+    /// Checks every guard out of `state`, and returns the first transition whose guard has held
+    /// for its debounce, if any. A guard that doesn't hold starts over.
+    pub fn evaluate(&mut self, state: &RocketState, inputs: &GuardInputs) -> Option<Transition> {
+        let id = state.id();
+        let mut transition = None;
+        for (index, rule) in TRANSITIONS.iter().enumerate() {
+            let counter = &mut self.counters[index];
+            let reason = if rule.from.contains(&id) {
+                (rule.guard)(state, inputs)
+            } else {
+                None
+            };
+            let Some(reason) = reason else {
+                *counter = GuardCounter::default();
+                continue;
+            };
+            counter.samples += 1;
+            let held = inputs
+                .now
+                .duration_since(*counter.since.get_or_insert(inputs.now));
+
+            let debounce = self.settings[index];
+            if transition.is_none()
+                && counter.samples >= debounce.samples
+                && held >= Duration::from_millis(debounce.duration_ms)
+            {
+                let reason = if debounce == DebounceConfig::NONE {
+                    reason
+                } else {
+                    format!(
+                        "{} (held for {} samples, {:.3} s)",
+                        reason,
+                        counter.samples,
+                        held.as_secs_f32()
+                    )
+                };
+                transition = Some(Transition {
+                    state: state.enter(rule.to, inputs),
+                    reason,
+                    action: rule.action,
+                });
+            }
+        }
+
+        // The new state's guards start from scratch:
+        if transition.is_some() {
+            self.counters.fill(GuardCounter::default());
+        }
+        transition
+    }
+
+    /// The guards that hold but haven't fired yet.
+    pub fn pending(&self, now: Instant) -> Vec<DebounceStatus> {
+        TRANSITIONS
+            .iter()
+            .zip(&self.settings)
+            .zip(&self.counters)
+            .filter_map(|((rule, debounce), counter)| {
+                let since = counter.since?;
+                Some(DebounceStatus {
+                    key: rule.key,
+                    samples: counter.samples,
+                    required_samples: debounce.samples,
+                    held_ms: now.duration_since(since).as_millis() as u64,
+                    required_ms: debounce.duration_ms,
+                })
+            })
+            .collect()
+    }
+}
+
+/// States that no chain of transitions from Standby leads to.
+pub fn unreachable_states() -> Vec<StateId> {
+    let mut reached = BTreeSet::from([StateId::Standby]);
+    let mut to_visit = vec![StateId::Standby];
+    while let Some(state) = to_visit.pop() {
+        for rule in TRANSITIONS.iter().filter(|rule| rule.from.contains(&state)) {
+            if reached.insert(rule.to) {
+                to_visit.push(rule.to);
+            }
+        }
+    }
+    StateId::ALL
+        .into_iter()
+        .filter(|state| !reached.contains(state))
+        .collect()
+}
+
+/// The transition table as a Mermaid state diagram, with the debounce `debouncer` applies.
+pub fn diagram(debouncer: &Debouncer) -> String {
+    let mut out = String::from("stateDiagram-v2\n");
+    let _ = writeln!(out, "    [*] --> {}", StateId::Standby.name());
+    for (rule, debounce) in TRANSITIONS.iter().zip(&debouncer.settings) {
+        let mut label = format!("{} ({}", rule.guard_name, rule.key);
+        if debounce.samples > 1 {
+            let _ = write!(label, ", {} samples", debounce.samples);
+        }
+        if debounce.duration_ms > 0 {
+            let _ = write!(label, ", {} ms", debounce.duration_ms);
+        }
+        label.push(')');
+        if rule.action != TransitionAction::None {
+            let _ = write!(label, " / {}", rule.action.name());
+        }
+        for from in rule.from {
+            let _ = writeln!(out, "    {} --> {}: {}", from.name(), rule.to.name(), label);
+        }
+    }
+    let _ = writeln!(out, "    {} --> [*]", StateId::Shutdown.name());
+    out
+}
+
+impl TransitionAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransitionAction::None => "none",
+            TransitionAction::ReportPyroStatus => "report pyro status",
+            TransitionAction::FirePyros(PyroTrigger::Apogee) => "fire apogee pyros",
+            TransitionAction::FirePyros(PyroTrigger::Main) => "fire main pyros",
+            TransitionAction::DisarmPyros => "disarm pyros",
+        }
+    }
+}
+
+fn abort_command(_: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    (inputs.command == Some("SALT ABORT")).then(|| String::from("SALT ABORT command"))
+}
+
+fn safety_abort(_: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match inputs.safety_trip {
+        Some(SafetyTrip::Abort(reason)) => Some(reason.clone()),
+        _ => None,
+    }
+}
+
+fn safety_fault(_: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match inputs.safety_trip {
+        Some(SafetyTrip::Fault(reason)) => Some(reason.clone()),
+        _ => None,
+    }
+}
+
+fn boot_command(_: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    (inputs.command == Some("SALT BOOT")).then(|| String::from("SALT BOOT command"))
+}
+
+fn launch(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::Countdown(state) => state.launch_reason(inputs),
+        _ => None,
+    }
+}
+
+fn burnout(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::MotorBurn(state) => state.burnout_reason(inputs),
+        _ => None,
+    }
+}
+
+fn apogee(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::CoastState(state) => state.apogee_reason(inputs),
+        _ => None,
+    }
+}
+
+fn coast_timeout(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::CoastState(state) => state.coast_timeout_reason(inputs),
+        _ => None,
+    }
+}
+
+fn landed(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::DrogueDescent(state) => state.landed_reason(inputs),
+        RocketState::MainDescent(state) => state.landed_reason(inputs),
+        _ => None,
+    }
+}
+
+fn main_deploy(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::DrogueDescent(state) => state.main_deploy_reason(inputs),
+        _ => None,
+    }
+}
+
+fn settled_with_beacon(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::Landed(state) if inputs.config.recovery.beacon_enabled => state
+            .settled_reason(inputs)
+            .map(|reason| format!("{}, starting the recovery beacon", reason)),
+        _ => None,
+    }
+}
+
+fn settled_without_beacon(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::Landed(state) if !inputs.config.recovery.beacon_enabled => state
+            .settled_reason(inputs)
+            .map(|reason| format!("{}, recovery beacon disabled", reason)),
+        _ => None,
+    }
+}
+
+fn beacon_done(state: &RocketState, inputs: &GuardInputs) -> Option<String> {
+    match state {
+        RocketState::Recovery(state) => state.beacon_done_reason(inputs),
+        RocketState::Abort(state) => state.beacon_done_reason(inputs),
+        RocketState::Fault(state) => state.beacon_done_reason(inputs),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::{
+        APOGEE_VELOCITY_SAMPLES, BURNOUT_SAMPLES, GRAVITY_METERS_PER_SECOND_SQUARED,
+        LANDED_WINDOW_SAMPLES, LAUNCH_ACCELERATION_SAMPLES, MAX_COAST_SECONDS,
+    };
+    use crate::state::{CountdownState, DrogueDescentState, MotorBurnState, StandbyState};
+
+    /// A rocket sitting still on the pad.
+    fn packet() -> ProcessorDataPacket {
+        ProcessorDataPacket {
+            current_altitude: 0.0,
+            vertical_velocity: 0.0,
+            maximum_altitude: 0.0,
+            maximum_velocity: 0.0,
+            acceleration: [0.0, 0.0, GRAVITY_METERS_PER_SECOND_SQUARED],
+            high_g_weight: 0.0,
+            mach_lockout: false,
+        }
+    }
+
+    fn inputs<'a>(
+        now: Instant,
+        processor: &'a ProcessorDataPacket,
+        config: &'a FlightConfig,
+    ) -> GuardInputs<'a> {
+        GuardInputs {
+            now,
+            processor,
+            config,
+            command: None,
+            safety_trip: None,
+        }
+    }
+
+    fn rule(key: &str) -> &'static TransitionRule {
+        TRANSITIONS.iter().find(|rule| rule.key == key).unwrap()
+    }
+
+    /// Checks that the rule applies to `state` and its guard holds, and returns the state it
+    /// enters.
+    fn fire(key: &str, state: &RocketState, inputs: &GuardInputs) -> RocketState {
+        let rule = rule(key);
+        assert!(
+            rule.from.contains(&state.id()),
+            "{} doesn't leave {}",
+            key,
+            state.name()
+        );
+        assert!(
+            (rule.guard)(state, inputs).is_some(),
+            "{} didn't hold in {}",
+            key,
+            state.name()
+        );
+        let entered = state.enter(rule.to, inputs);
+        assert_eq!(entered.id(), rule.to);
+        entered
+    }
+
+    fn holds(key: &str, state: &RocketState, inputs: &GuardInputs) -> bool {
+        (rule(key).guard)(state, inputs).is_some()
+    }
+
+    fn coast(burnout_velocity: f32, now: Instant, config: &FlightConfig) -> RocketState {
+        let packet = ProcessorDataPacket {
+            vertical_velocity: burnout_velocity,
+            ..packet()
+        };
+        let burn = RocketState::MotorBurn(MotorBurnState::new(now));
+        burn.enter(StateId::Coast, &inputs(now, &packet, config))
+    }
+
+    fn drogue_descent() -> RocketState {
+        RocketState::DrogueDescent(DrogueDescentState::new())
+    }
+
+    #[test]
+    fn every_state_is_reachable() {
+        assert!(
+            unreachable_states().is_empty(),
+            "{:?}",
+            unreachable_states()
+        );
+    }
+
+    #[test]
+    fn abort_command() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let mut inputs = inputs(Instant::now(), &packet, &config);
+        let standby = RocketState::Standby(StandbyState {});
+        assert!(!holds("abort_command", &standby, &inputs));
+
+        inputs.command = Some("SALT ABORT");
+        assert!(matches!(
+            fire("abort_command", &standby, &inputs),
+            RocketState::Abort(_)
+        ));
+        let countdown = RocketState::Countdown(CountdownState::new());
+        assert!(matches!(
+            fire("abort_command", &countdown, &inputs),
+            RocketState::Abort(_)
+        ));
+        // Never after launch, the parachutes still have to come out:
+        assert!(!rule("abort_command").from.contains(&StateId::Coast));
+    }
+
+    #[test]
+    fn safety_abort() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let trip = SafetyTrip::Abort(String::from("tilted"));
+        let mut inputs = inputs(Instant::now(), &packet, &config);
+        let countdown = RocketState::Countdown(CountdownState::new());
+        assert!(!holds("safety_abort", &countdown, &inputs));
+
+        inputs.safety_trip = Some(&trip);
+        assert!(matches!(
+            fire("safety_abort", &countdown, &inputs),
+            RocketState::Abort(_)
+        ));
+        assert!(!holds("safety_fault", &countdown, &inputs));
+        assert!(!rule("safety_abort").from.contains(&StateId::MotorBurn));
+    }
+
+    #[test]
+    fn safety_fault() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let trip = SafetyTrip::Fault(String::from("BMP280 altitude failed to read"));
+        let mut inputs = inputs(Instant::now(), &packet, &config);
+        let countdown = RocketState::Countdown(CountdownState::new());
+        assert!(!holds("safety_fault", &countdown, &inputs));
+
+        inputs.safety_trip = Some(&trip);
+        assert!(matches!(
+            fire("safety_fault", &countdown, &inputs),
+            RocketState::Fault(_)
+        ));
+        assert!(!holds("safety_abort", &countdown, &inputs));
+        assert!(!rule("safety_fault").from.contains(&StateId::DrogueDescent));
+    }
+
+    #[test]
+    fn boot_command() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let mut inputs = inputs(Instant::now(), &packet, &config);
+        let standby = RocketState::Standby(StandbyState {});
+        inputs.command = Some("SALT WAIT");
+        assert!(!holds("boot_command", &standby, &inputs));
+
+        inputs.command = Some("SALT BOOT");
+        assert!(matches!(
+            fire("boot_command", &standby, &inputs),
+            RocketState::Countdown(_)
+        ));
+    }
+
+    #[test]
+    fn launch() {
+        let config = FlightConfig::default();
+        let mut countdown = RocketState::Countdown(CountdownState::new());
+        let thrust = ProcessorDataPacket {
+            acceleration: [0.0, 0.0, 50.0],
+            ..packet()
+        };
+        for _ in 0..LAUNCH_ACCELERATION_SAMPLES {
+            countdown.update_internal(&thrust);
+        }
+        let now = Instant::now();
+        // Acceleration alone isn't enough, the velocity or altitude has to confirm it:
+        assert!(!holds("launch", &countdown, &inputs(now, &thrust, &config)));
+
+        let climbing = ProcessorDataPacket {
+            vertical_velocity: 15.0,
+            ..thrust
+        };
+        let RocketState::MotorBurn(burn) =
+            fire("launch", &countdown, &inputs(now, &climbing, &config))
+        else {
+            panic!("launch didn't enter MotorBurn");
+        };
+        // Liftoff is when the acceleration started, not when the launch was confirmed:
+        assert!(burn.liftoff_time <= now);
+    }
+
+    #[test]
+    fn burnout() {
+        let config = FlightConfig::default();
+        let liftoff = Instant::now();
+        let mut burn = RocketState::MotorBurn(MotorBurnState::new(liftoff));
+        let coasting = ProcessorDataPacket {
+            vertical_velocity: 100.0,
+            acceleration: [0.0, 0.0, 2.0],
+            ..packet()
+        };
+        for _ in 0..BURNOUT_SAMPLES - 1 {
+            burn.update_internal(&coasting);
+        }
+        let now = liftoff + Duration::from_secs(2);
+        assert!(!holds("burnout", &burn, &inputs(now, &coasting, &config)));
+
+        burn.update_internal(&coasting);
+        let RocketState::CoastState(coast) =
+            fire("burnout", &burn, &inputs(now, &coasting, &config))
+        else {
+            panic!("burnout didn't enter Coast");
+        };
+        assert!(coast.burnout_time >= liftoff && coast.burnout_time <= now);
+    }
+
+    #[test]
+    fn apogee() {
+        let config = FlightConfig::default();
+        let now = Instant::now();
+        let mut coast = coast(100.0, now, &config);
+        let falling = ProcessorDataPacket {
+            current_altitude: 495.0,
+            vertical_velocity: -2.0,
+            maximum_altitude: 500.0,
+            ..packet()
+        };
+        assert!(!holds("apogee", &coast, &inputs(now, &falling, &config)));
+
+        for _ in 0..APOGEE_VELOCITY_SAMPLES {
+            coast.update_internal(&falling);
+        }
+        let locked_out = ProcessorDataPacket {
+            mach_lockout: true,
+            ..falling
+        };
+        assert!(!holds("apogee", &coast, &inputs(now, &locked_out, &config)));
+        let entered = fire("apogee", &coast, &inputs(now, &falling, &config));
+        assert!(matches!(entered, RocketState::DrogueDescent(_)));
+        assert_eq!(
+            rule("apogee").action,
+            TransitionAction::FirePyros(PyroTrigger::Apogee)
+        );
+    }
+
+    #[test]
+    fn coast_timeout() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let now = Instant::now();
+        let coast = coast(100.0, now, &config);
+        assert!(!holds(
+            "coast_timeout",
+            &coast,
+            &inputs(now, &packet, &config)
+        ));
+
+        let later = now + Duration::from_secs_f32(MAX_COAST_SECONDS);
+        let entered = fire("coast_timeout", &coast, &inputs(later, &packet, &config));
+        assert!(matches!(entered, RocketState::DrogueDescent(_)));
+        assert_eq!(
+            rule("coast_timeout").action,
+            TransitionAction::FirePyros(PyroTrigger::Apogee)
+        );
+    }
+
+    #[test]
+    fn landed() {
+        let config = FlightConfig::default();
+        let mut drogue = drogue_descent();
+        let descending = ProcessorDataPacket {
+            current_altitude: 300.0,
+            vertical_velocity: -20.0,
+            ..packet()
+        };
+        for _ in 0..LANDED_WINDOW_SAMPLES {
+            drogue.update_internal(&descending);
+        }
+        let now = Instant::now();
+        assert!(!holds(
+            "landed",
+            &drogue,
+            &inputs(now, &descending, &config)
+        ));
+
+        let still = ProcessorDataPacket {
+            current_altitude: -20.0,
+            ..packet()
+        };
+        for _ in 0..LANDED_WINDOW_SAMPLES {
+            drogue.update_internal(&still);
+        }
+        assert!(matches!(
+            fire("landed", &drogue, &inputs(now, &still, &config)),
+            RocketState::Landed(_)
+        ));
+    }
+
+    #[test]
+    fn main_deploy() {
+        let config = FlightConfig::default();
+        let now = Instant::now();
+        let mut drogue = drogue_descent();
+        let high = ProcessorDataPacket {
+            current_altitude: config.deployment.main_deploy_altitude + 1.0,
+            ..packet()
+        };
+        assert!(!holds("main_deploy", &drogue, &inputs(now, &high, &config)));
+
+        // Stationary on the way down, e.g. hung up in a tree:
+        let low = ProcessorDataPacket {
+            current_altitude: 100.0,
+            ..packet()
+        };
+        for _ in 0..LANDED_WINDOW_SAMPLES {
+            drogue.update_internal(&low);
+        }
+        let main = fire("main_deploy", &drogue, &inputs(now, &low, &config));
+        assert!(matches!(main, RocketState::MainDescent(_)));
+        assert_eq!(
+            rule("main_deploy").action,
+            TransitionAction::FirePyros(PyroTrigger::Main)
+        );
+        // The landing detection carries on from the drogue descent:
+        assert!(holds("landed", &main, &inputs(now, &low, &config)));
+    }
+
+    #[test]
+    fn settled() {
+        let mut config = FlightConfig::default();
+        let packet = packet();
+        let now = Instant::now();
+        let landed = drogue_descent().enter(StateId::Landed, &inputs(now, &packet, &config));
+        assert!(!holds(
+            "settled_with_beacon",
+            &landed,
+            &inputs(now, &packet, &config)
+        ));
+
+        let later = now + Duration::from_secs(5);
+        let entered = fire(
+            "settled_with_beacon",
+            &landed,
+            &inputs(later, &packet, &config),
+        );
+        assert!(matches!(entered, RocketState::Recovery(_)));
+        assert!(!holds(
+            "settled_without_beacon",
+            &landed,
+            &inputs(later, &packet, &config)
+        ));
+
+        config.recovery.beacon_enabled = false;
+        let entered = fire(
+            "settled_without_beacon",
+            &landed,
+            &inputs(later, &packet, &config),
+        );
+        assert!(matches!(entered, RocketState::Shutdown));
+        assert!(!holds(
+            "settled_with_beacon",
+            &landed,
+            &inputs(later, &packet, &config)
+        ));
+    }
+
+    #[test]
+    fn beacon_done() {
+        let config = FlightConfig::default();
+        let packet = packet();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(config.recovery.beacon_duration_seconds);
+        for id in [StateId::Recovery, StateId::Abort, StateId::Fault] {
+            let state = drogue_descent().enter(id, &inputs(now, &packet, &config));
+            assert!(!holds(
+                "beacon_done",
+                &state,
+                &inputs(now, &packet, &config)
+            ));
+            let entered = fire("beacon_done", &state, &inputs(later, &packet, &config));
+            assert!(matches!(entered, RocketState::Shutdown));
+        }
+    }
+
+    #[test]
+    fn debounce_waits_for_the_guard_to_hold() {
+        let config = FlightConfig::default();
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(&config.debounce);
+        let low = ProcessorDataPacket {
+            current_altitude: 100.0,
+            ..packet()
+        };
+        let high = ProcessorDataPacket {
+            current_altitude: 1000.0,
+            ..packet()
+        };
+        let drogue = drogue_descent();
+
+        // One noisy reading, then the count starts over:
+        assert!(
+            debouncer
+                .evaluate(&drogue, &inputs(now, &low, &config))
+                .is_none()
+        );
+        assert_eq!(debouncer.pending(now)[0].key, "main_deploy");
+        assert!(
+            debouncer
+                .evaluate(&drogue, &inputs(now, &high, &config))
+                .is_none()
+        );
+        assert!(debouncer.pending(now).is_empty());
+
+        for _ in 0..2 {
+            assert!(
+                debouncer
+                    .evaluate(&drogue, &inputs(now, &low, &config))
+                    .is_none()
+            );
+        }
+        let transition = debouncer
+            .evaluate(&drogue, &inputs(now, &low, &config))
+            .unwrap();
+        assert!(matches!(transition.state, RocketState::MainDescent(_)));
+        assert!(debouncer.pending(now).is_empty());
+    }
+
+    #[test]
+    fn debounce_overrides() {
+        let mut config = FlightConfig::default();
+        config.debounce.insert(
+            String::from("main_deploy"),
+            DebounceConfig {
+                samples: 1,
+                duration_ms: 100,
+            },
+        );
+        let now = Instant::now();
+        let mut debouncer = Debouncer::new(&config.debounce);
+        let low = ProcessorDataPacket {
+            current_altitude: 100.0,
+            ..packet()
+        };
+        let drogue = drogue_descent();
+
+        assert!(
+            debouncer
+                .evaluate(&drogue, &inputs(now, &low, &config))
+                .is_none()
+        );
+        let later = now + Duration::from_millis(100);
+        assert!(
+            debouncer
+                .evaluate(&drogue, &inputs(later, &low, &config))
+                .is_some()
+        );
+
+        // The diagram shows what's flown:
+        let diagram = diagram(&debouncer);
+        assert!(diagram.contains("(main_deploy, 100 ms)"));
+        assert!(diagram.contains("(apogee, 3 samples)"));
+    }
+
+    #[test]
+    fn commands_ignore_debounce_overrides() {
+        let mut config = FlightConfig::default();
+        for key in COMMAND_GUARDS {
+            config.debounce.insert(
+                String::from(*key),
+                DebounceConfig {
+                    samples: 3,
+                    duration_ms: 500,
+                },
+            );
+        }
+        let mut debouncer = Debouncer::new(&config.debounce);
+        let packet = packet();
+        let standby = RocketState::Standby(StandbyState {});
+
+        // A command is only seen once, and still has to go through:
+        let mut abort = inputs(Instant::now(), &packet, &config);
+        abort.command = Some("SALT ABORT");
+        let transition = debouncer.evaluate(&standby, &abort).unwrap();
+        assert!(matches!(transition.state, RocketState::Abort(_)));
+
+        let mut boot = inputs(Instant::now(), &packet, &config);
+        boot.command = Some("SALT BOOT");
+        let transition = debouncer.evaluate(&standby, &boot).unwrap();
+        assert!(matches!(transition.state, RocketState::Countdown(_)));
+
+        assert!(!diagram(&debouncer).contains("(abort_command, 3 samples"));
+    }
+
+    #[test]
+    fn debounce_rejects_unknown_fields() {
+        let config = "[debounce.apogee]\nsample = 5\n";
+        assert!(toml::from_str::<FlightConfig>(config).is_err());
+        let config = "[debounce.apogee]\nsamples = 5\n";
+        let config: FlightConfig = toml::from_str(config).unwrap();
+        assert_eq!(config.debounce["apogee"], DebounceConfig::samples(5));
+    }
+}
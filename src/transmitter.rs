@@ -2,7 +2,12 @@ use std::error::Error;
 use std::io::Read;
 use std::io::Write;
 
-use serialport::TTYPort;
+use crate::events::Event;
+use crate::gps::GpsDataPacket;
+use crate::state::state_letter;
+use crate::transitions::DebounceStatus;
+use crate::warn;
+use serialport::{SerialPort, TTYPort};
 pub struct TransmitterDataPacket {
     pub state_name: &'static str,
     pub alt: f32,
@@ -12,9 +17,28 @@ pub struct TransmitterDataPacket {
     pub gyro: [f32; 3],  // Gyroscope data in rad/s (x, y, z)
 }
 
+/// Sent at a low rate after landing, to help find the rocket.
+pub struct BeaconDataPacket {
+    pub gps: Option<GpsDataPacket>,
+    pub battery_voltage: Option<f32>,
+    pub max_alt: f32,
+    pub max_vel: f32,
+    pub flight_time: f32, // Seconds from launch to landing
+}
+
+/// Counters for the telemetry link, saved in the flight summary.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinkStats {
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    pub send_failures: u64,
+    pub commands_received: u64,
+}
+
 pub struct Transmitter {
     port: TTYPort,
     buffer: String,
+    stats: LinkStats,
 }
 
 impl Transmitter {
@@ -24,12 +48,16 @@ impl Transmitter {
             .open_native()
             .expect("Failed to open serial port");
 
-        Transmitter { port, buffer: String::new() }
+        Transmitter {
+            port,
+            buffer: String::new(),
+            stats: LinkStats::default(),
+        }
     }
 
     pub fn transmit(&mut self, data_packet: &TransmitterDataPacket) {
         // TODO: Add the callsign to the output string
-        let state_letter = data_packet.state_name.chars().next().unwrap_or('U');
+        let state_letter = state_letter(data_packet.state_name);
         let output = format!(
             "{},{:.1},{:.1},{:.1},{:.1},{:.1},{:.1},{:.1}\n",
             state_letter,
@@ -43,9 +71,131 @@ impl Transmitter {
             data_packet.gyro[2]
         );
 
-        match self.port.write_all(output.as_bytes()) {
+        match self.send(&output) {
             Ok(_) => (),
-            Err(_) => eprintln!("Failed to write to port for transmission"),
+            Err(_) => warn!("Failed to write to port for transmission"),
+        }
+    }
+
+    /// Sends the GPS position on its own line, so it doesn't change the main telemetry format.
+    pub fn transmit_gps(&mut self, gps_data_packet: &GpsDataPacket) {
+        let output = format!(
+            "G,{:.6},{:.6},{:.1},{},{}\n",
+            gps_data_packet.latitude,
+            gps_data_packet.longitude,
+            gps_data_packet.altitude,
+            gps_data_packet.fix_quality,
+            gps_data_packet.satellites
+        );
+
+        match self.send(&output) {
+            Ok(_) => (),
+            Err(_) => warn!("Failed to write GPS position to port for transmission"),
+        }
+    }
+
+    /// Sends the recovery beacon. Fields we don't have (no GPS fix, no battery monitor) are left
+    /// empty.
+    pub fn transmit_beacon(&mut self, beacon_data_packet: &BeaconDataPacket) {
+        let position = match &beacon_data_packet.gps {
+            Some(gps) => format!(
+                "{:.6},{:.6},{},{}",
+                gps.latitude, gps.longitude, gps.fix_quality, gps.satellites
+            ),
+            None => String::from(",,,"),
+        };
+        let battery = beacon_data_packet
+            .battery_voltage
+            .map(|voltage| format!("{:.2}", voltage))
+            .unwrap_or_default();
+        let output = format!(
+            "B,{},{},{:.1},{:.1},{:.1}\n",
+            position,
+            battery,
+            beacon_data_packet.max_alt,
+            beacon_data_packet.max_vel,
+            beacon_data_packet.flight_time
+        );
+
+        match self.send(&output) {
+            Ok(_) => (),
+            Err(_) => warn!("Failed to write beacon to port for transmission"),
+        }
+    }
+
+    /// Sends an event on its own line. Commas in the payload are replaced so the line can still be
+    /// split on them.
+    pub fn transmit_event(&mut self, event: &Event) {
+        let output = format!(
+            "E,{:.3},{},{},{}\n",
+            event.timestamp as f64 / 1e9,
+            event.severity.name(),
+            event.kind.name(),
+            event.payload.replace([',', '\n'], ";")
+        );
+
+        match self.send(&output) {
+            Ok(_) => (),
+            Err(_) => warn!("Failed to write event to port for transmission"),
+        }
+    }
+
+    /// Sends the debounce counters of the guards that hold, for tuning, as
+    /// `T,<guard>:<samples>/<required samples>:<held ms>/<required ms>,...`.
+    pub fn transmit_debounce(&mut self, pending: &[DebounceStatus]) {
+        let guards: Vec<String> = pending
+            .iter()
+            .map(|status| {
+                format!(
+                    "{}:{}/{}:{}/{}",
+                    status.key,
+                    status.samples,
+                    status.required_samples,
+                    status.held_ms,
+                    status.required_ms
+                )
+            })
+            .collect();
+        let output = format!("T,{}\n", guards.join(","));
+
+        match self.send(&output) {
+            Ok(_) => (),
+            Err(_) => warn!("Failed to write debounce counters to port for transmission"),
+        }
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    fn send(&mut self, output: &str) -> std::io::Result<()> {
+        let result = self.port.write_all(output.as_bytes());
+        match result {
+            Ok(()) => {
+                self.stats.packets_sent += 1;
+                self.stats.bytes_sent += output.len() as u64;
+            }
+            Err(_) => self.stats.send_failures += 1,
+        }
+        result
+    }
+
+    /// Waits for everything queued to be sent, then closes the port.
+    pub fn close(mut self) {
+        if let Err(e) = self.port.flush() {
+            warn!("Failed to flush transmitter port: {}", e);
+        }
+    }
+
+    /// Returns a command if one has arrived, without waiting for one like `read` does.
+    pub fn poll_command(&mut self) -> Option<String> {
+        match self.port.bytes_to_read() {
+            Ok(bytes) if bytes > 0 => {}
+            _ => return None,
+        }
+        match self.read() {
+            Ok(command) if command != "wait" => Some(command),
+            _ => None,
         }
     }
 
@@ -74,6 +224,7 @@ impl Transmitter {
                         if command.is_empty() {
                             Ok(String::from("wait"))
                         } else {
+                            self.stats.commands_received += 1;
                             Ok(command)
                         }
                     } else {